@@ -0,0 +1,27 @@
+#![allow(dead_code)]
+//! Compares day 12 counting algorithms:
+//! bench12 [unfold factor] [files...]
+//! default is factor 5 on all day 12 inputs.
+use std::env;
+
+#[path = "../day12.rs"]
+mod day12;
+#[path = "../rng.rs"]
+mod rng;
+
+fn main() {
+    let factor = env::args()
+        .nth(1)
+        .map(|s| s.parse().expect("Invalid unfold factor"))
+        .unwrap_or(5);
+    let mut files: Vec<String> = env::args().skip(2).collect();
+    if files.is_empty() {
+        files = vec![
+            "data/input-day12-sample.txt".to_string(),
+            "data/input-day12.txt".to_string(),
+        ];
+    }
+    for file_name in &files {
+        day12::benchmark(file_name, factor);
+    }
+}
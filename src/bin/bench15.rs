@@ -0,0 +1,21 @@
+#![allow(dead_code)]
+//! Compares day 15 box implementations:
+//! bench15 [number of operations] [number of labels]
+use std::env;
+
+#[path = "../day15.rs"]
+mod day15;
+#[path = "../rng.rs"]
+mod rng;
+
+fn main() {
+    let count = env::args()
+        .nth(1)
+        .map(|s| s.parse().expect("Invalid number of operations"))
+        .unwrap_or(1_000_000);
+    let labels = env::args()
+        .nth(2)
+        .map(|s| s.parse().expect("Invalid number of labels"))
+        .unwrap_or(100_000);
+    day15::benchmark(count, labels);
+}
@@ -1,43 +1,38 @@
+#![allow(dead_code)]
+//! Converts day 18 dig plan between encodings:
+//! conv <input> <output> [literal|hex] [literal|hex]
+//! default is from literal to hex, so that part 1 plans can be used with part 2 solution.
 use std::{
     env,
     fs::File,
-    io::{BufRead, BufReader, BufWriter, Write},
+    io::{BufReader, BufWriter},
 };
 
+#[path = "../day18.rs"]
+mod day18;
+#[path = "../geometry.rs"]
+mod geometry;
+
+use day18::Encoding;
+
 fn main() {
     // open file and read
     let file_name = env::args().nth(1).expect("Missing file name");
     let f = BufReader::new(File::open(file_name).expect("Problem opening file"));
 
-    let mut out = BufWriter::new(
+    let out = BufWriter::new(
         File::create(env::args().nth(2).expect("Missing output file name"))
             .expect("Problem creating file"),
     );
 
-    for line in f.lines() {
-        let line = line.expect("Problem reading line");
-        let mut items = line.split_whitespace();
-        let (dir, steps, _color) = (
-            items.next().unwrap(),
-            items.next().unwrap().parse::<u32>().unwrap(),
-            items.next().unwrap(),
-        );
-
-        let dir_num = match dir {
-            "R" => 0,
-            "D" => 1,
-            "L" => 2,
-            "U" => 3,
-            _ => panic!("Bad direction"),
-        };
-
-        let new_color = dir_num + 16 * steps;
-
-        let new_line = format!("{} {} (#{:06x})", dir, steps, new_color);
+    let from: Encoding = env::args()
+        .nth(3)
+        .map(|s| s.parse().expect("Invalid source encoding"))
+        .unwrap_or(Encoding::Literal);
+    let to: Encoding = env::args()
+        .nth(4)
+        .map(|s| s.parse().expect("Invalid target encoding"))
+        .unwrap_or(Encoding::Hex);
 
-        out.write_all(new_line.as_bytes())
-            .expect("Problem writing line");
-        out.write_all("\n".as_bytes())
-            .expect("Problem writing line");
-    }
+    day18::convert(f, out, from, to).expect("Problem converting plan");
 }
@@ -0,0 +1,14 @@
+#![allow(dead_code)]
+//! Interactive day 15 HASHMAP - type `label=N` or `label-` commands,
+//! state of boxes is printed after each of them.
+use std::io;
+
+#[path = "../day15.rs"]
+mod day15;
+#[path = "../rng.rs"]
+mod rng;
+
+fn main() {
+    let power = day15::repl(io::stdin().lock(), io::stdout()).expect("Problem reading commands");
+    println!("Final focusing power: {}", power);
+}
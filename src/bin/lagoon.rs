@@ -0,0 +1,24 @@
+#![allow(dead_code)]
+//! Draws day 18 lagoon dug by part 1 plan as PPM image:
+//! lagoon <input> <output.ppm>
+use std::{
+    env,
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+#[path = "../day18.rs"]
+mod day18;
+#[path = "../geometry.rs"]
+mod geometry;
+
+fn main() {
+    let file_name = env::args().nth(1).expect("Missing file name");
+    let f = BufReader::new(File::open(file_name).expect("Problem opening file"));
+    let out = BufWriter::new(
+        File::create(env::args().nth(2).expect("Missing output file name"))
+            .expect("Problem creating file"),
+    );
+    let size = day18::eighteens_render(f, out).expect("Problem drawing lagoon");
+    println!("Lagoon size: {}", size);
+}
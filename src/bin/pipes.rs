@@ -0,0 +1,24 @@
+#![allow(dead_code)]
+//! Renders day 10 pipe map with enclosed cells shaded:
+//! pipes <input> <output>
+use std::{
+    env,
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+#[path = "../day10.rs"]
+mod day10;
+#[path = "../geometry.rs"]
+mod geometry;
+
+fn main() {
+    let file_name = env::args().nth(1).expect("Missing file name");
+    let f = BufReader::new(File::open(file_name).expect("Problem opening file"));
+    let out = BufWriter::new(
+        File::create(env::args().nth(2).expect("Missing output file name"))
+            .expect("Problem creating file"),
+    );
+    let enclosed = day10::tenth_render(f, out).expect("Problem rendering map");
+    println!("Enclosed cells: {}", enclosed);
+}
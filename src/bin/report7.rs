@@ -0,0 +1,39 @@
+#![allow(dead_code)]
+//! Prints day 7 ranking report with part 2 rules:
+//! report7 <input> [rank|cards|hand|bet|winnings] [csv output]
+//! sort key prefixed with '-' sorts in descending order, default is by rank.
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+};
+
+#[path = "../day7.rs"]
+mod day7;
+#[path = "../rng.rs"]
+mod rng;
+
+use day7::SortKey;
+
+fn main() {
+    let file_name = env::args().nth(1).expect("Missing file name");
+    let f = BufReader::new(File::open(file_name).expect("Problem opening file"));
+
+    let key = env::args().nth(2).unwrap_or_else(|| "rank".to_string());
+    let descending = key.starts_with('-');
+    let key: SortKey = key
+        .trim_start_matches('-')
+        .parse()
+        .expect("Problem parsing sort key");
+
+    let csv: Box<dyn Write> = match env::args().nth(3) {
+        Some(csv_name) => Box::new(BufWriter::new(
+            File::create(csv_name).expect("Problem creating file"),
+        )),
+        None => Box::new(io::sink()),
+    };
+
+    let total = day7::seventh_report(f, key, descending, io::stdout(), csv)
+        .expect("Problem writing report");
+    println!("Total winnings: {}", total);
+}
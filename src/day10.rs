@@ -1,4 +1,10 @@
-use std::{fmt::Display, io::BufRead};
+use crate::geometry;
+use anyhow::{bail, Context};
+use colored::{ColoredString, Colorize};
+use std::{
+    fmt::Display,
+    io::{BufRead, Write},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Direction {
@@ -48,8 +54,45 @@ enum Cell {
 }
 
 impl Cell {
-    fn leads_north(&self) -> bool {
-        matches!(self, Cell::N_S | Cell::N_E | Cell::N_W)
+    /// Box drawing character for the pipe
+    fn glyph(&self) -> char {
+        match self {
+            Cell::Empty => ' ',
+            Cell::N_S => '│',
+            Cell::E_W => '─',
+            Cell::N_E => '└',
+            Cell::N_W => '┘',
+            Cell::S_W => '┐',
+            Cell::S_E => '┌',
+            Cell::Start => 'S',
+        }
+    }
+
+    /// Directions, where the pipe leads
+    fn connections(&self) -> Option<(Direction, Direction)> {
+        match self {
+            Cell::N_S => Some((Direction::N, Direction::S)),
+            Cell::E_W => Some((Direction::E, Direction::W)),
+            Cell::N_E => Some((Direction::N, Direction::E)),
+            Cell::N_W => Some((Direction::N, Direction::W)),
+            Cell::S_W => Some((Direction::S, Direction::W)),
+            Cell::S_E => Some((Direction::S, Direction::E)),
+            Cell::Empty | Cell::Start => None,
+        }
+    }
+
+    /// Pipe leading to given directions (in any order)
+    fn from_connections(a: Direction, b: Direction) -> Option<Cell> {
+        [
+            Cell::N_S,
+            Cell::E_W,
+            Cell::N_E,
+            Cell::N_W,
+            Cell::S_W,
+            Cell::S_E,
+        ]
+        .into_iter()
+        .find(|c| c.connections() == Some((a, b)) || c.connections() == Some((b, a)))
     }
 }
 
@@ -80,68 +123,154 @@ struct Map {
 }
 
 impl Map {
-    fn parse<R: BufRead>(reader: R) -> Self {
+    /// Parses map and replaces start with the pipe shape it stands for
+    fn parse<R: BufRead>(reader: R) -> Result<Self, anyhow::Error> {
         let mut map = Vec::new();
-        let mut row_no = 0;
         let mut start: Option<(usize, usize)> = None;
-        for line in reader.lines() {
-            let line = line.unwrap();
-
-            map.push(
-                line.chars()
-                    .map(|c| c.try_into().unwrap())
-                    .enumerate()
-                    .map(|(i, c)| {
-                        if c == Cell::Start {
-                            start = Some((row_no, i));
-                        }
-                        TrackedCell {
-                            cell: c,
-                            visited: false,
-                        }
-                    })
-                    .collect::<Vec<TrackedCell>>(),
-            );
-            row_no += 1;
+        for (row_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut row = Vec::with_capacity(line.len());
+            for (i, c) in line.chars().enumerate() {
+                let cell = Cell::try_from(c)?;
+                if cell == Cell::Start {
+                    start = Some((row_no, i));
+                }
+                row.push(TrackedCell {
+                    cell,
+                    visited: false,
+                });
+            }
+            map.push(row);
         }
 
-        let width = map[0].len();
+        let width = map.first().context("Empty map")?.len();
         let height = map.len();
-        let start = start.unwrap();
-        Map {
+        let start = start.context("No start in map")?;
+        let mut map = Map {
             map,
             width,
             height,
             start,
+        };
+        map.infer_start()?;
+        Ok(map)
+    }
+
+    /// Directions from start towards neighbours, which have a pipe connected back to start
+    fn start_connections(&self) -> Vec<Direction> {
+        [Direction::N, Direction::S, Direction::E, Direction::W]
+            .into_iter()
+            .filter(|dir| {
+                self.move_to(self.start, *dir)
+                    .and_then(|pos| self.map[pos.0][pos.1].cell.next_cell_direction(*dir))
+                    .is_some()
+            })
+            .collect()
+    }
+
+    /// Follows pipes from start heading in given direction, until start is reached again.
+    /// Returns visited cells (starting with start) and direction, in which start was entered,
+    /// or None if route leads to a dead end.
+    fn trace_from_start(&self, dir: Direction) -> Option<(Vec<(usize, usize)>, Direction)> {
+        let mut route = vec![self.start];
+        let mut pos = self.start;
+        let mut heading = dir;
+        loop {
+            pos = self.move_to(pos, heading)?;
+            if pos == self.start {
+                return Some((route, heading));
+            }
+            route.push(pos);
+            heading = self.map[pos.0][pos.1].cell.next_cell_direction(heading)?;
         }
     }
 
-    fn starting_points(&mut self) -> (Route, Route) {
-        let mut res = vec![];
-        self.map[self.start.0][self.start.1].visited = true;
-        for dir in [Direction::N, Direction::S, Direction::E, Direction::W] {
-            if let Some(pos) = self.move_to(self.start, dir) {
-                if let Some(next_direction) = self.map[pos.0][pos.1].cell.next_cell_direction(dir) {
-                    self.map[pos.0][pos.1].visited = true;
-                    {
-                        res.push({
-                            Route {
-                                len: 1,
-                                next_direction,
-                                pos,
-                            }
-                        })
+    /// Replaces start cell with pipe shape, which closes the loop going through start
+    fn infer_start(&mut self) -> Result<Cell, anyhow::Error> {
+        let connections = self.start_connections();
+        for dir in &connections {
+            if let Some((_, entered)) = self.trace_from_start(*dir) {
+                let shape = Cell::from_connections(*dir, entered.inverse())
+                    .context("Loop enters start from the side it left")?;
+                self.map[self.start.0][self.start.1].cell = shape;
+                return Ok(shape);
+            }
+        }
+        bail!(
+            "No loop goes through start at {:?}, connected directions {:?}",
+            self.start,
+            connections
+        )
+    }
+
+    /// Main loop as ordered list of cells, starting at start
+    fn main_loop(&self) -> Vec<(usize, usize)> {
+        let (first, _) = self.map[self.start.0][self.start.1]
+            .cell
+            .connections()
+            .expect("Start shape is inferred when parsing");
+        let (route, _) = self
+            .trace_from_start(first)
+            .expect("Start shape closes the loop");
+        route
+    }
+
+    /// Marks main loop as visited and computes farthest distance and enclosed tiles
+    fn solve(&mut self) -> LoopSummary {
+        let polygon = self.main_loop();
+        for (row, col) in &polygon {
+            self.map[*row][*col].visited = true;
+        }
+        let farthest = polygon.len() as u64 / 2;
+        let enclosed = self.enclosed_by(&polygon);
+        LoopSummary {
+            farthest,
+            enclosed,
+            polygon,
+        }
+    }
+
+    /// Counts cells enclosed by loop polygon - using Pick's theorem
+    fn enclosed_by(&self, polygon: &[(usize, usize)]) -> u64 {
+        geometry::interior_points(&corners(polygon)) as u64
+    }
+
+    /// Draws map with main loop in box drawing characters, enclosed cells shaded dark
+    /// and outside cells shaded light. Loop must be already visited (see `solve`).
+    fn render(&self, color: bool) -> String {
+        let polygon = corners(&self.main_loop());
+        let mut out = String::new();
+        for (r, row) in self.map.iter().enumerate() {
+            for (c, cell) in row.iter().enumerate() {
+                let tile: ColoredString = if cell.visited {
+                    let glyph = cell.cell.glyph().to_string();
+                    if !color {
+                        glyph.into()
+                    } else if (r, c) == self.start {
+                        glyph.red().bold()
+                    } else {
+                        glyph.yellow().bold()
                     }
+                } else if geometry::contains(&polygon, (r as i64, c as i64)) {
+                    "▓".green()
+                } else {
+                    "░".bright_black()
+                };
+                if color {
+                    out.push_str(&tile.to_string());
+                } else {
+                    out.push_str(&tile);
                 }
             }
+            out.push('\n');
         }
-        assert!(
-            res.len() == 2,
-            "Expected exactly 2 starting points, got {}",
-            res.len()
-        );
-        let mut iter = res.into_iter();
-        (iter.next().unwrap(), iter.next().unwrap())
+        out
+    }
+
+    /// Writes rendering without colors
+    fn write_render(&self, mut out: impl Write) -> Result<(), anyhow::Error> {
+        out.write_all(self.render(false).as_bytes())?;
+        Ok(())
     }
 
     fn move_to(&self, pos: (usize, usize), dir: Direction) -> Option<(usize, usize)> {
@@ -162,17 +291,7 @@ impl Map {
 
 impl Display for Map {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in &self.map {
-            for cell in row {
-                if cell.visited {
-                    write!(f, "#")?;
-                } else {
-                    write!(f, ".")?;
-                }
-            }
-            write!(f, "\n")?;
-        }
-        Ok(())
+        write!(f, "{}", self.render(true))
     }
 }
 
@@ -198,66 +317,95 @@ impl Cell {
     }
 }
 
+/// Loop cells reduced to vertices, where the loop turns
+fn corners(polygon: &[(usize, usize)]) -> Vec<geometry::Point> {
+    let points = polygon
+        .iter()
+        .map(|(row, col)| (*row as i64, *col as i64))
+        .collect::<Vec<_>>();
+    let n = points.len();
+    (0..n)
+        .filter(|i| {
+            let (prev, p, next) = (points[(i + n - 1) % n], points[*i], points[(i + 1) % n]);
+            (p.0 - prev.0, p.1 - prev.1) != (next.0 - p.0, next.1 - p.1)
+        })
+        .map(|i| points[i])
+        .collect()
+}
+
+/// Main loop and values derived from it
 #[derive(Debug)]
-struct Route {
-    len: u64,
-    next_direction: Direction,
-    pos: (usize, usize),
+struct LoopSummary {
+    /// Steps to the loop cell farthest from start
+    farthest: u64,
+    /// Number of cells enclosed by the loop
+    enclosed: u64,
+    /// Loop cells in order, starting at start
+    polygon: Vec<(usize, usize)>,
 }
 
-impl Route {
-    fn move_next(&mut self, map: &mut Map) {
-        let new_pos = map.move_to(self.pos, self.next_direction).unwrap();
-        let next_cell = &mut map.map[new_pos.0][new_pos.1];
-        let old_direction = self.next_direction;
-        self.next_direction = next_cell
-            .cell
-            .next_cell_direction(self.next_direction)
-            .expect(&format!(
-                "No route at {:?} towards {:?}",
-                new_pos, self.next_direction,
-            ));
-        // println!(
-        //     "moved from {:?} direction {:?} to {:?}, then can go {:?}",
-        //     self.pos, old_direction, new_pos, self.next_direction
-        // );
-        self.pos = new_pos;
-        self.len += 1;
-        map.map[new_pos.0][new_pos.1].visited = true;
-    }
+pub fn tenth_task_1(f: impl BufRead) -> u64 {
+    let mut map = Map::parse(f).unwrap();
+    map.solve().farthest
 }
 
 pub fn tenth_task_2(f: impl BufRead) -> u64 {
-    let mut map = Map::parse(f);
-    println!("map: {:?}", map);
-    let (mut r1, mut r2) = map.starting_points();
-    println!("r1: {:?}, r2: {:?}", r1, r2);
-    let max_depth = loop {
-        r1.move_next(&mut map);
-        if r1.pos == r2.pos {
-            break r1.len.min(r2.len);
-        }
-        r2.move_next(&mut map);
-        if r1.pos == r2.pos {
-            break r1.len.min(r2.len);
-        }
-    };
+    let mut map = Map::parse(f).unwrap();
+    let summary = map.solve();
     println!("map:\n{}", map);
-    let mut inside_count = 0;
-    for (r, row) in map.map.iter().enumerate() {
-        let mut north_cells = 0;
-        for (c, cell) in row.iter().enumerate() {
-            if cell.visited {
-                if cell.cell.leads_north() {
-                    north_cells += 1
-                };
-            } else {
-                if north_cells % 2 == 1 {
-                    println!("INSIDE {} {}", r, c);
-                    inside_count += 1;
-                }
-            }
-        }
+    summary.enclosed
+}
+
+/// Writes map with shaded inside and outside of the loop, returns number of enclosed cells
+pub fn tenth_render(f: impl BufRead, out: impl Write) -> Result<u64, anyhow::Error> {
+    let mut map = Map::parse(f)?;
+    let summary = map.solve();
+    map.write_render(out)?;
+    Ok(summary.enclosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::BufReader};
+
+    fn load_map(name: &str) -> Map {
+        let f = BufReader::new(File::open(format!("data/{}", name)).unwrap());
+        Map::parse(f).unwrap()
+    }
+
+    #[test]
+    fn test_start_shape() {
+        let map = load_map("input-day10-sample.txt");
+        assert_eq!(map.map[2][0].cell, Cell::S_E);
+        let map = load_map("input-day10-sample3.txt");
+        assert_eq!(map.map[4][12].cell, Cell::S_E);
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut map = load_map("input-day10-sample.txt");
+        let summary = map.solve();
+        assert_eq!(summary.farthest, 8);
+        assert_eq!(summary.polygon.len(), 16);
+        assert_eq!(summary.polygon[0], (2, 0));
+
+        let mut map = load_map("input-day10-sample2.txt");
+        assert_eq!(map.solve().enclosed, 4);
+        let mut map = load_map("input-day10-sample3.txt");
+        assert_eq!(map.solve().enclosed, 8);
+        // shading by point in polygon test must agree with Pick's theorem
+        assert_eq!(map.render(false).matches('▓').count(), 8);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut map = load_map("input-day10-sample2.txt");
+        map.solve();
+        let rendered = map.render(false);
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "░┌───────┐░");
+        assert_eq!(lines[6], "░│▓▓│░│▓▓│░");
+        assert_eq!(rendered.matches('▓').count(), 4);
     }
-    inside_count
 }
@@ -1,4 +1,11 @@
-use std::io::BufRead;
+use crate::rng::Rng;
+use anyhow::Context;
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    io::BufRead,
+    ops::{Add, Sub},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Token {
@@ -20,6 +27,16 @@ impl TryFrom<char> for Token {
     }
 }
 
+impl From<Token> for char {
+    fn from(token: Token) -> Self {
+        match token {
+            Token::Working => '.',
+            Token::Damaged => '#',
+            Token::Unknown => '?',
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 struct State {
     pos: usize,
@@ -27,49 +44,449 @@ struct State {
     group_size: u16,
 }
 
-type Cache = std::collections::HashMap<State, u64>;
+type Cache<N = u64> = std::collections::HashMap<State, N>;
+
+/// Number type for counting arrangements - counting needs only zero, one and addition,
+/// subtraction and comparison are used to pick arrangement by its index
+trait Number: Clone + Ord + Add<Output = Self> + Sub<Output = Self> + From<u8> {
+    /// Uniformly random number in 0..n
+    fn random_below(rng: &mut Rng, n: &Self) -> Self;
+}
+
+impl Number for u64 {
+    fn random_below(rng: &mut Rng, n: &Self) -> Self {
+        rng.below(*n)
+    }
+}
+
+impl Number for Count {
+    fn random_below(rng: &mut Rng, n: &Self) -> Self {
+        if let Count::Small(n) = n {
+            return Count::Small(rng.below(*n));
+        }
+        let digits = n.digits();
+        let top = *digits.last().unwrap();
+        // mask of bits up to the highest bit of n, so that at least half of tries succeed
+        let mask = u32::MAX >> top.leading_zeros();
+        loop {
+            let mut random: Vec<u32> = digits.iter().map(|_| rng.next() as u32).collect();
+            *random.last_mut().unwrap() &= mask;
+            let random = Count::from_digits(random);
+            if random < *n {
+                return random;
+            }
+        }
+    }
+}
+
+/// Arrangements count, which switches to arbitrary precision, when it would overflow u64
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Count {
+    Small(u64),
+    /// Little endian base 2^32 digits
+    Big(Vec<u32>),
+}
+
+impl Count {
+    /// Digits without leading zeros
+    fn digits(&self) -> Vec<u32> {
+        match self {
+            Count::Small(n) => {
+                let mut digits = vec![*n as u32, (*n >> 32) as u32];
+                while digits.last() == Some(&0) {
+                    digits.pop();
+                }
+                digits
+            }
+            Count::Big(digits) => digits.clone(),
+        }
+    }
+
+    /// Small count if it fits to u64, so that every number has single representation
+    fn from_digits(mut digits: Vec<u32>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        if digits.len() <= 2 {
+            let low = *digits.first().unwrap_or(&0) as u64;
+            let high = *digits.get(1).unwrap_or(&0) as u64;
+            Count::Small(low | (high << 32))
+        } else {
+            Count::Big(digits)
+        }
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        match self {
+            Count::Small(n) => Some(*n),
+            Count::Big(_) => None,
+        }
+    }
+}
+
+impl From<u8> for Count {
+    fn from(n: u8) -> Self {
+        Count::Small(n as u64)
+    }
+}
+
+impl Add for Count {
+    type Output = Count;
+
+    fn add(self, other: Self) -> Self::Output {
+        if let (Count::Small(a), Count::Small(b)) = (&self, &other) {
+            if let Some(sum) = a.checked_add(*b) {
+                return Count::Small(sum);
+            }
+        }
+        let (a, b) = (self.digits(), other.digits());
+        let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..a.len().max(b.len()) {
+            let s = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+            sum.push(s as u32);
+            carry = s >> 32;
+        }
+        if carry > 0 {
+            sum.push(carry as u32);
+        }
+        Count::from_digits(sum)
+    }
+}
+
+impl Sub for Count {
+    type Output = Count;
 
-fn process_mem(cache: &mut Cache, pattern: &[Token], groups: &[u16], state: State) -> u64 {
+    /// Panics if other is bigger
+    fn sub(self, other: Self) -> Self::Output {
+        if let (Count::Small(a), Count::Small(b)) = (&self, &other) {
+            return Count::Small(a - b);
+        }
+        assert!(self >= other, "Count subtraction overflow");
+        let (a, b) = (self.digits(), other.digits());
+        let mut diff = Vec::with_capacity(a.len());
+        let mut borrow = 0i64;
+        for (i, d) in a.iter().enumerate() {
+            let mut s = *d as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = (s < 0) as i64;
+            if s < 0 {
+                s += 1 << 32;
+            }
+            diff.push(s as u32);
+        }
+        Count::from_digits(diff)
+    }
+}
+
+impl Ord for Count {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.digits(), other.digits());
+        a.len()
+            .cmp(&b.len())
+            .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+    }
+}
+
+impl PartialOrd for Count {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Count {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Count::Small(n) => write!(f, "{}", n),
+            Count::Big(digits) => {
+                // repeatedly divide by 10^9 to get decimal digits in chunks
+                const CHUNK: u64 = 1_000_000_000;
+                let mut digits = digits.clone();
+                let mut chunks = vec![];
+                while digits.iter().any(|d| *d > 0) {
+                    let mut rem = 0u64;
+                    for d in digits.iter_mut().rev() {
+                        let cur = (rem << 32) | *d as u64;
+                        *d = (cur / CHUNK) as u32;
+                        rem = cur % CHUNK;
+                    }
+                    chunks.push(rem);
+                }
+                match chunks.split_last() {
+                    None => write!(f, "0"),
+                    Some((first, rest)) => {
+                        write!(f, "{}", first)?;
+                        for chunk in rest.iter().rev() {
+                            write!(f, "{:09}", chunk)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn process_mem<N: Number>(
+    cache: &mut Cache<N>,
+    pattern: &[Token],
+    groups: &[u16],
+    state: State,
+) -> N {
     if let Some(res) = cache.get(&state) {
         // println!("For state {:?} result is {}", state, res);
-        return *res;
+        return res.clone();
     }
     let res = process_rec(cache, pattern, groups, state.clone());
-    cache.insert(state, res);
+    cache.insert(state, res.clone());
     res
 }
 
-fn process_rec(cache: &mut Cache, pattern: &[Token], groups: &[u16], mut state: State) -> u64 {
-    if state.pos >= pattern.len() {
-        if state.group_pos == groups.len() && state.group_size == 0 {
-            1
-        } else if state.group_pos == groups.len() - 1 && state.group_size == groups[state.group_pos]
-        {
-            1
-        } else {
-            0
-        }
-    } else {
-        let mut res = 0;
+/// Are all groups complete at the end of pattern
+fn is_final(groups: &[u16], state: &State) -> bool {
+    (state.group_pos == groups.len() && state.group_size == 0)
+        || (state.group_pos + 1 == groups.len() && state.group_size == groups[state.group_pos])
+}
 
-        let current = pattern[state.pos];
-        state.pos += 1;
-        if current == Token::Working || current == Token::Unknown {
-            let mut state = state.clone();
+/// State after the next cell is resolved as working or damaged, None if it breaks groups
+fn next_state(groups: &[u16], state: &State, token: Token) -> Option<State> {
+    let mut state = state.clone();
+    state.pos += 1;
+    match token {
+        Token::Working => {
             if state.group_size == 0 {
-                res += process_mem(cache, pattern, groups, state);
+                Some(state)
             } else if state.group_pos < groups.len() && groups[state.group_pos] == state.group_size
             {
                 state.group_pos += 1;
                 state.group_size = 0;
-                res += process_mem(cache, pattern, groups, state);
+                Some(state)
+            } else {
+                None
             }
         }
-        if current == Token::Damaged || current == Token::Unknown {
+        Token::Damaged => {
             state.group_size += 1;
-            res += process_mem(cache, pattern, groups, state);
+            Some(state)
+        }
+        Token::Unknown => unreachable!("Unknown token has to be resolved"),
+    }
+}
+
+/// Possible resolutions of a cell, damaged first so that arrangements are in the order
+/// of their string representation
+fn options(token: Token) -> &'static [Token] {
+    match token {
+        Token::Working => &[Token::Working],
+        Token::Damaged => &[Token::Damaged],
+        Token::Unknown => &[Token::Damaged, Token::Working],
+    }
+}
+
+fn process_rec<N: Number>(
+    cache: &mut Cache<N>,
+    pattern: &[Token],
+    groups: &[u16],
+    state: State,
+) -> N {
+    if state.pos >= pattern.len() {
+        N::from(is_final(groups, &state) as u8)
+    } else {
+        options(pattern[state.pos])
+            .iter()
+            .filter_map(|token| next_state(groups, &state, *token))
+            .map(|next| process_mem(cache, pattern, groups, next))
+            .fold(N::from(0), |acc, n| acc + n)
+    }
+}
+
+/// Repeats pattern and groups given number of times, copies of pattern are joined by separator
+fn unfold(
+    pattern: &[Token],
+    groups: &[u16],
+    factor: usize,
+    separator: Token,
+) -> (Vec<Token>, Vec<u16>) {
+    let mut ext_pattern = Vec::with_capacity((pattern.len() + 1) * factor);
+    for i in 0..factor {
+        if i > 0 {
+            ext_pattern.push(separator);
+        }
+        ext_pattern.extend_from_slice(pattern);
+    }
+    (ext_pattern, groups.repeat(factor))
+}
+
+/// Exact number of arrangements, even if it does not fit to u64
+fn count_exact(pattern: &[Token], groups: &[u16]) -> Count {
+    let mut cache = Cache::new();
+    process_rec(&mut cache, pattern, groups, State::default())
+}
+
+/// Counts arrangements iteratively over dense table, where cell (i, j) is number
+/// of arrangements of pattern[i..] with groups[j..], cell i not continuing previous group.
+/// Row n + 1 stands for the end of pattern after group, which ended at the last cell.
+fn count_dense<N: Number>(pattern: &[Token], groups: &[u16]) -> N {
+    let n = pattern.len();
+    let m = groups.len();
+    // number of working cells in pattern[..i], to check quickly if group can be placed
+    let mut working = vec![0; n + 1];
+    for i in 0..n {
+        working[i + 1] = working[i] + (pattern[i] == Token::Working) as usize;
+    }
+    let width = m + 1;
+    let mut table = vec![N::from(0); (n + 2) * width];
+    table[n * width + m] = N::from(1);
+    table[(n + 1) * width + m] = N::from(1);
+    for i in (0..n).rev() {
+        for j in 0..=m {
+            let mut count = N::from(0);
+            if pattern[i] != Token::Damaged {
+                count = count + table[(i + 1) * width + j].clone();
+            }
+            if j < m && pattern[i] != Token::Working {
+                let end = i + groups[j] as usize;
+                if end <= n
+                    && working[end] == working[i]
+                    && (end == n || pattern[end] != Token::Damaged)
+                {
+                    count = count + table[(end + 1) * width + j + 1].clone();
+                }
+            }
+            table[i * width + j] = count;
+        }
+    }
+    table[0].clone()
+}
+
+/// Counts lines with dense table, lines are split among available cores
+fn count_parallel(lines: &[(Vec<Token>, Vec<u16>)]) -> Vec<Count> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = lines.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = lines
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(pattern, groups)| count_dense::<Count>(pattern, groups))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Counting thread panicked"))
+            .collect()
+    })
+}
+
+fn parse_line(line: &str) -> Result<(Vec<Token>, Vec<u16>), anyhow::Error> {
+    let mut iter = line.split_ascii_whitespace();
+    let pattern = iter
+        .next()
+        .context("Missing pattern")?
+        .chars()
+        .map(Token::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let groups = iter
+        .next()
+        .context("Missing groups")?
+        .split(',')
+        .map(|x| x.parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((pattern, groups))
+}
+
+fn to_string(arrangement: &[Token]) -> String {
+    arrangement.iter().map(|t| char::from(*t)).collect()
+}
+
+/// Counts of arrangements for one row, remembered for all visited states
+struct Arrangements<'a, N: Number = u64> {
+    pattern: &'a [Token],
+    groups: &'a [u16],
+    cache: Cache<N>,
+}
+
+impl<'a, N: Number + 'a> Arrangements<'a, N> {
+    fn new(pattern: &'a [Token], groups: &'a [u16]) -> Self {
+        Arrangements {
+            pattern,
+            groups,
+            cache: Cache::new(),
+        }
+    }
+
+    /// Number of ways to complete arrangement from given state
+    fn count_from(&mut self, state: &State) -> N {
+        process_mem(&mut self.cache, self.pattern, self.groups, state.clone())
+    }
+
+    fn count(&mut self) -> N {
+        self.count_from(&State::default())
+    }
+
+    /// Arrangement with given index (from 0), in order of their string representation
+    fn nth(&mut self, mut index: N) -> Option<Vec<Token>> {
+        let mut state = State::default();
+        let mut arrangement = Vec::with_capacity(self.pattern.len());
+        if index >= self.count() {
+            return None;
+        }
+        while state.pos < self.pattern.len() {
+            let mut chosen = None;
+            for token in options(self.pattern[state.pos]) {
+                if let Some(next) = next_state(self.groups, &state, *token) {
+                    let count = self.count_from(&next);
+                    if index < count {
+                        chosen = Some((*token, next));
+                        break;
+                    }
+                    index = index - count;
+                }
+            }
+            let (token, next) = chosen.expect("Index is within count");
+            arrangement.push(token);
+            state = next;
         }
-        res
+        Some(arrangement)
+    }
+
+    /// Uniformly random arrangement
+    fn sample(&mut self, rng: &mut Rng) -> Option<Vec<Token>> {
+        let count = self.count();
+        if count == N::from(0) {
+            None
+        } else {
+            self.nth(N::random_below(rng, &count))
+        }
+    }
+
+    /// Lazily enumerates arrangements in order of their string representation,
+    /// dead ends are skipped thanks to the counts
+    fn iter(mut self) -> impl Iterator<Item = Vec<Token>> + 'a {
+        let mut stack = vec![(State::default(), vec![])];
+        std::iter::from_fn(move || {
+            while let Some((state, arrangement)) = stack.pop() {
+                if state.pos >= self.pattern.len() {
+                    return Some(arrangement);
+                }
+                // reversed, so that first option is on top of the stack
+                for token in options(self.pattern[state.pos]).iter().rev() {
+                    if let Some(next) = next_state(self.groups, &state, *token) {
+                        if self.count_from(&next) > N::from(0) {
+                            let mut arrangement = arrangement.clone();
+                            arrangement.push(*token);
+                            stack.push((next, arrangement));
+                        }
+                    }
+                }
+            }
+            None
+        })
     }
 }
 
@@ -77,21 +494,10 @@ pub fn twelveth_task_1(f: impl BufRead) -> u64 {
     let mut sum = 0;
     for line in f.lines() {
         let line = line.unwrap();
-        let mut iter = line.split_ascii_whitespace();
-        let pattern = iter.next().unwrap();
-        let groups: Vec<_> = iter
-            .next()
-            .unwrap()
-            .split(',')
-            .map(|x| x.parse::<u16>().unwrap())
-            .collect();
-        let pattern2 = pattern
-            .chars()
-            .map(|x| x.try_into().unwrap())
-            .collect::<Vec<_>>();
-        let mut cache = std::collections::HashMap::new();
-        let variants = process_rec(&mut cache, &pattern2, &groups, State::default());
-        println!("{} {:?} => {}", pattern, groups, variants);
+        let (pattern, groups) = parse_line(&line).unwrap();
+        let mut arrangements: Arrangements = Arrangements::new(&pattern, &groups);
+        let variants = arrangements.count();
+        println!("{} => {}", line, variants);
         sum += variants;
     }
 
@@ -99,35 +505,400 @@ pub fn twelveth_task_1(f: impl BufRead) -> u64 {
 }
 
 pub fn twelveth_task_2(f: impl BufRead) -> u64 {
-    let mut sum = 0;
-    for line in f.lines() {
-        let line = line.unwrap();
-        let mut iter = line.split_ascii_whitespace();
-        let pattern = iter.next().unwrap();
-        let mut ext_pattern = pattern.to_string();
-        for _ in 0..4 {
-            ext_pattern.push('?');
-            ext_pattern.push_str(pattern);
-        }
-        let groups: Vec<_> = iter
-            .next()
-            .unwrap()
-            .split(',')
-            .map(|x| x.parse::<u16>().unwrap())
+    let sum = unfolded_sum(f, 5, Token::Unknown);
+    sum.to_u64().expect("Sum fits u64")
+}
+
+/// Sum of arrangements of all lines unfolded with given factor and separator
+fn unfolded_sum(f: impl BufRead, factor: usize, separator: Token) -> Count {
+    let lines = unfolded_lines(f, factor, separator);
+    let sum = count_parallel(&lines)
+        .into_iter()
+        .fold(Count::from(0), |acc, n| acc + n);
+    println!("Sum for unfold factor {} is {}", factor, sum);
+    sum
+}
+
+fn unfolded_lines(f: impl BufRead, factor: usize, separator: Token) -> Vec<(Vec<Token>, Vec<u16>)> {
+    f.lines()
+        .map(|line| {
+            let (pattern, groups) = parse_line(&line.unwrap()).unwrap();
+            unfold(&pattern, &groups, factor, separator)
+        })
+        .collect()
+}
+
+/// Compares recursive counting with memo and dense table on lines of given file,
+/// prints times and panics if results differ
+pub fn benchmark(file_name: &str, factor: usize) {
+    let f = std::io::BufReader::new(std::fs::File::open(file_name).expect("Problem opening file"));
+    let lines = unfolded_lines(f, factor, Token::Unknown);
+
+    let start = std::time::Instant::now();
+    let recursive: Vec<Count> = lines
+        .iter()
+        .map(|(pattern, groups)| count_exact(pattern, groups))
+        .collect();
+    let recursive_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let dense: Vec<Count> = lines
+        .iter()
+        .map(|(pattern, groups)| count_dense(pattern, groups))
+        .collect();
+    let dense_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let parallel = count_parallel(&lines);
+    let parallel_time = start.elapsed();
+
+    assert_eq!(recursive, dense, "Dense table differs from recursion");
+    assert_eq!(
+        recursive, parallel,
+        "Parallel counting differs from recursion"
+    );
+    let sum = dense.into_iter().fold(Count::from(0), |acc, n| acc + n);
+    println!(
+        "{} x{}: sum {}, recursive {:?}, dense {:?}, dense parallel {:?}",
+        file_name, factor, sum, recursive_time, dense_time, parallel_time
+    );
+}
+
+/// Nonogram (picross) solver - every row and column is a day 12 line
+mod nonogram {
+    use super::*;
+    use std::fmt::Display;
+
+    type Grid = Vec<Vec<Token>>;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum Solution {
+        Unique(Picture),
+        /// Two different solutions found
+        Multiple(Picture, Picture),
+        None,
+    }
+
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub struct Picture(Grid);
+
+    impl Display for Picture {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            for row in &self.0 {
+                writeln!(f, "{}", to_string(row))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Nonogram {
+        rows: Vec<Vec<u16>>,
+        cols: Vec<Vec<u16>>,
+    }
+
+    /// Resolves unknown cells, which are the same in all arrangements of the line.
+    /// Returns if anything changed, None if line has no arrangement.
+    fn propagate_line(line: &mut [Token], clue: &[u16]) -> Option<bool> {
+        if Arrangements::<u64>::new(line, clue).count() == 0 {
+            return None;
+        }
+        let mut changed = false;
+        for i in 0..line.len() {
+            if line[i] != Token::Unknown {
+                continue;
+            }
+            line[i] = Token::Damaged;
+            let can_be_damaged = Arrangements::<u64>::new(line, clue).count() > 0;
+            line[i] = Token::Working;
+            let can_be_working = Arrangements::<u64>::new(line, clue).count() > 0;
+            line[i] = match (can_be_damaged, can_be_working) {
+                (true, false) => Token::Damaged,
+                (false, true) => Token::Working,
+                _ => Token::Unknown,
+            };
+            changed |= line[i] != Token::Unknown;
+        }
+        Some(changed)
+    }
+
+    impl Nonogram {
+        pub fn new(rows: Vec<Vec<u16>>, cols: Vec<Vec<u16>>) -> Self {
+            Nonogram { rows, cols }
+        }
+
+        /// Row clues, empty line, column clues - one comma separated clue per line,
+        /// 0 for an empty line of the picture
+        pub fn parse(f: impl BufRead) -> Result<Self, anyhow::Error> {
+            let mut clues: [Vec<Vec<u16>>; 2] = [vec![], vec![]];
+            let mut part = 0;
+            for line in f.lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() {
+                    part += 1;
+                    if part > 1 {
+                        break;
+                    }
+                    continue;
+                }
+                let clue = line
+                    .split(',')
+                    .map(|x| x.trim().parse::<u16>())
+                    .filter(|x| x != &Ok(0))
+                    .collect::<Result<Vec<_>, _>>()?;
+                clues[part].push(clue);
+            }
+            let [rows, cols] = clues;
+            if rows.is_empty() || cols.is_empty() {
+                anyhow::bail!("Both row and column clues are required");
+            }
+            Ok(Nonogram { rows, cols })
+        }
+
+        /// Applies line logic to rows and columns until nothing changes,
+        /// None if there is a contradiction
+        fn propagate(&self, grid: &mut Grid) -> Option<()> {
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (row, clue) in grid.iter_mut().zip(&self.rows) {
+                    changed |= propagate_line(row, clue)?;
+                }
+                for (c, clue) in self.cols.iter().enumerate() {
+                    let mut col: Vec<Token> = grid.iter().map(|row| row[c]).collect();
+                    if propagate_line(&mut col, clue)? {
+                        changed = true;
+                        for (row, token) in grid.iter_mut().zip(col) {
+                            row[c] = token;
+                        }
+                    }
+                }
+            }
+            Some(())
+        }
+
+        /// Propagates and then guesses first unknown cell, stops after two solutions
+        fn search(&self, mut grid: Grid, solutions: &mut Vec<Picture>) {
+            if self.propagate(&mut grid).is_none() {
+                return;
+            }
+            let unknown = grid.iter().enumerate().find_map(|(r, row)| {
+                row.iter()
+                    .position(|t| *t == Token::Unknown)
+                    .map(|c| (r, c))
+            });
+            match unknown {
+                None => solutions.push(Picture(grid)),
+                Some((r, c)) => {
+                    for token in [Token::Damaged, Token::Working] {
+                        if solutions.len() >= 2 {
+                            break;
+                        }
+                        let mut guess = grid.clone();
+                        guess[r][c] = token;
+                        self.search(guess, solutions);
+                    }
+                }
+            }
+        }
+
+        pub fn solve(&self) -> Solution {
+            let grid = vec![vec![Token::Unknown; self.cols.len()]; self.rows.len()];
+            let mut solutions = vec![];
+            self.search(grid, &mut solutions);
+            let mut solutions = solutions.into_iter();
+            match (solutions.next(), solutions.next()) {
+                (Some(first), Some(second)) => Solution::Multiple(first, second),
+                (Some(first), None) => Solution::Unique(first),
+                _ => Solution::None,
+            }
+        }
+    }
+}
+
+/// Solves nonogram, returns number of solutions found (2 means there are more)
+pub fn nonogram_task(f: impl BufRead) -> u64 {
+    let nonogram = nonogram::Nonogram::parse(f).unwrap();
+    match nonogram.solve() {
+        nonogram::Solution::Unique(picture) => {
+            println!("{}", picture);
+            1
+        }
+        nonogram::Solution::Multiple(first, second) => {
+            println!("Multiple solutions, e.g.:\n{}\n{}", first, second);
+            2
+        }
+        nonogram::Solution::None => {
+            println!("No solution");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arrangements() {
+        let (pattern, groups) = parse_line("?###???????? 3,2,1").unwrap();
+        let mut arrangements: Arrangements = Arrangements::new(&pattern, &groups);
+        assert_eq!(arrangements.count(), 10);
+        let all: Vec<String> = Arrangements::<u64>::new(&pattern, &groups)
+            .iter()
+            .map(|a| to_string(&a))
             .collect();
-        let mut ext_groups = groups.clone();
-        for _ in 0..4 {
-            ext_groups.extend(groups.iter());
-        }
-        let ext_pattern = ext_pattern
-            .chars()
-            .map(|x| x.try_into().unwrap())
-            .collect::<Vec<_>>();
-        let mut cache = std::collections::HashMap::new();
-        let variants = process_rec(&mut cache, &ext_pattern, &ext_groups, State::default());
-        println!("{} {:?} => {}", pattern, groups, variants);
-        sum += variants;
+        assert_eq!(all.len(), 10);
+        assert_eq!(all[0], ".###.##.#...");
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(all, sorted);
+        for (i, a) in all.iter().enumerate() {
+            assert_eq!(to_string(&arrangements.nth(i as u64).unwrap()), *a);
+        }
+        assert!(arrangements.nth(10).is_none());
+        let limited = Arrangements::<u64>::new(&pattern, &groups)
+            .iter()
+            .take(3)
+            .count();
+        assert_eq!(limited, 3);
+
+        let mut rng = Rng::new(42);
+        for _ in 0..20 {
+            let sample = to_string(&arrangements.sample(&mut rng).unwrap());
+            assert!(all.contains(&sample));
+        }
     }
 
-    sum
+    #[test]
+    fn test_no_arrangement() {
+        let (pattern, groups) = parse_line("#.# 3").unwrap();
+        let mut arrangements: Arrangements = Arrangements::new(&pattern, &groups);
+        assert_eq!(arrangements.count(), 0);
+        assert!(arrangements.sample(&mut Rng::new(1)).is_none());
+        assert_eq!(
+            Arrangements::<u64>::new(&pattern, &groups).iter().count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_unfold() {
+        let (pattern, groups) = parse_line("?? 1").unwrap();
+        // n groups of 1 in 3n-1 cells
+        let (ext_pattern, ext_groups) = unfold(&pattern, &groups, 5, Token::Unknown);
+        assert_eq!(to_string(&ext_pattern), "??????????????");
+        assert_eq!(count_exact(&ext_pattern, &ext_groups), Count::Small(252));
+        let (ext_pattern, ext_groups) = unfold(&pattern, &groups, 40, Token::Unknown);
+        let count = count_exact(&ext_pattern, &ext_groups);
+        assert!(count.to_u64().is_none());
+        assert_eq!(count.to_string(), "107507208733336176461620");
+
+        let (ext_pattern, ext_groups) = unfold(&pattern, &groups, 3, Token::Working);
+        assert_eq!(to_string(&ext_pattern), "??.??.??");
+        assert_eq!(count_exact(&ext_pattern, &ext_groups), Count::Small(8));
+
+        let data = std::fs::read_to_string("data/input-day12-sample.txt").unwrap();
+        assert_eq!(
+            unfolded_sum(data.as_bytes(), 5, Token::Unknown),
+            Count::Small(525152)
+        );
+        assert_eq!(
+            unfolded_sum(data.as_bytes(), 1, Token::Unknown),
+            Count::Small(21)
+        );
+    }
+
+    #[test]
+    fn test_big_arrangements() {
+        let (pattern, groups) = parse_line("?? 1").unwrap();
+        let (ext_pattern, ext_groups) = unfold(&pattern, &groups, 40, Token::Unknown);
+        let mut arrangements: Arrangements<Count> = Arrangements::new(&ext_pattern, &ext_groups);
+        let count = arrangements.count();
+        assert_eq!(count, count_exact(&ext_pattern, &ext_groups));
+        let valid = |a: &[Token]| {
+            let s = to_string(a);
+            s.matches('#').count() == 40 && !s.contains("##")
+        };
+        let last = arrangements.nth(count.clone() - Count::from(1)).unwrap();
+        assert!(valid(&last));
+        assert!(to_string(&last).ends_with('#'));
+        assert!(arrangements.nth(count.clone()).is_none());
+        let first = Arrangements::<Count>::new(&ext_pattern, &ext_groups)
+            .iter()
+            .next()
+            .unwrap();
+        assert_eq!(arrangements.nth(Count::from(0)).unwrap(), first);
+        let mut rng = Rng::new(7);
+        for _ in 0..5 {
+            assert!(valid(&arrangements.sample(&mut rng).unwrap()));
+        }
+
+        let big = count.clone() + count.clone();
+        assert!(big > count);
+        assert_eq!(big - count.clone(), count);
+        assert_eq!(
+            Count::Small(u64::MAX) + Count::from(1) - Count::from(1),
+            Count::Small(u64::MAX)
+        );
+        assert!(Count::from(1) < Count::Small(u64::MAX) + Count::from(1));
+    }
+
+    #[test]
+    fn test_dense() {
+        for file_name in ["data/input-day12-sample.txt", "data/input-day12.txt"] {
+            for factor in [1, 5] {
+                let f = std::fs::read(file_name).unwrap();
+                for (pattern, groups) in unfolded_lines(&f[..], factor, Token::Unknown) {
+                    assert_eq!(
+                        count_dense::<Count>(&pattern, &groups),
+                        count_exact(&pattern, &groups),
+                        "{} {:?}",
+                        to_string(&pattern),
+                        groups
+                    );
+                }
+            }
+        }
+        let (pattern, groups) = parse_line("?? 1").unwrap();
+        let (pattern, groups) = unfold(&pattern, &groups, 40, Token::Unknown);
+        assert_eq!(
+            count_dense::<Count>(&pattern, &groups).to_string(),
+            "107507208733336176461620"
+        );
+        assert_eq!(count_dense::<u64>(&[], &[]), 1);
+        assert_eq!(count_dense::<u64>(&[Token::Damaged], &[]), 0);
+    }
+
+    #[test]
+    fn test_nonogram() {
+        use nonogram::{Nonogram, Solution};
+        // letter H with a dot
+        let data = "1,1
+1,1
+3
+1,1
+1,1,1
+
+5
+1
+5
+0
+1
+";
+        let nonogram = Nonogram::parse(data.as_bytes()).unwrap();
+        match nonogram.solve() {
+            Solution::Unique(picture) => {
+                assert_eq!(picture.to_string(), "#.#..\n#.#..\n###..\n#.#..\n#.#.#\n")
+            }
+            other => panic!("Expected unique solution, got {:?}", other),
+        }
+
+        let ambiguous = Nonogram::new(vec![vec![1], vec![1]], vec![vec![1], vec![1]]);
+        assert!(matches!(ambiguous.solve(), Solution::Multiple(_, _)));
+
+        let impossible = Nonogram::new(vec![vec![2], vec![]], vec![vec![1], vec![]]);
+        assert_eq!(impossible.solve(), Solution::None);
+    }
 }
@@ -1,4 +1,4 @@
-use std::io::BufRead;
+use std::{fmt::Display, io::BufRead};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 enum Cell {
@@ -17,118 +17,407 @@ impl TryFrom<char> for Cell {
     }
 }
 
+/// Pattern with rows and columns encoded as bitmasks, bit is set for rock
 #[derive(Debug)]
 struct Mirror {
-    map: Vec<Vec<Cell>>,
+    /// Bit c of row r is cell (r, c)
+    rows: Vec<u64>,
+    /// Bit r of column c is cell (r, c)
+    cols: Vec<u64>,
     width: usize,
     height: usize,
 }
 
 impl Mirror {
-    fn parse<R: BufRead>(reader: &mut R) -> Option<Self> {
-        let mut map = Vec::new();
+    /// Next pattern from reader, None at the end of input
+    fn parse<R: BufRead>(reader: &mut R) -> Result<Option<Self>, anyhow::Error> {
+        let mut map: Vec<Vec<Cell>> = Vec::new();
 
         for line in reader.lines() {
-            let line = line.unwrap();
+            let line = line?;
             if line.is_empty() {
                 break;
             }
             let row = line
                 .chars()
-                .map(|c| c.try_into().unwrap())
-                .collect::<Vec<_>>();
+                .map(|c| c.try_into())
+                .collect::<Result<Vec<Cell>, _>>()?;
+            if let Some(first) = map.first() {
+                if row.len() != first.len() {
+                    anyhow::bail!("Pattern rows have different lengths");
+                }
+            }
             map.push(row);
         }
 
         if map.is_empty() {
-            return None;
+            return Ok(None);
         }
 
         let width = map[0].len();
         let height = map.len();
-        Some(Mirror { map, width, height })
-    }
-
-    fn transpose(self) -> Self {
-        let mut map = Vec::new();
-        for i in 0..self.width {
-            let mut row = Vec::new();
-            for j in 0..self.height {
-                row.push(self.map[j][i]);
+        if width > 64 || height > 64 {
+            anyhow::bail!("Pattern {}x{} does not fit bitmasks", width, height);
+        }
+        let mut rows = vec![0; height];
+        let mut cols = vec![0; width];
+        for (r, row) in map.iter().enumerate() {
+            for (c, cell) in row.iter().enumerate() {
+                if *cell == Cell::Rock {
+                    rows[r] |= 1 << c;
+                    cols[c] |= 1 << r;
+                }
             }
-            map.push(row);
         }
-        Mirror {
-            map,
-            width: self.height,
-            height: self.width,
+        Ok(Some(Mirror {
+            rows,
+            cols,
+            width,
+            height,
+        }))
+    }
+
+    /// Rows for horizontal lines, columns for vertical lines
+    fn lines(&self, orientation: Orientation) -> &[u64] {
+        match orientation {
+            Orientation::Horizontal => &self.rows,
+            Orientation::Vertical => &self.cols,
         }
     }
 
-    fn split(&self) -> Option<usize> {
-        'outer: for i in 1..self.map.len() {
-            let mut fixed = false;
-            let mut can_fix = |v1, v2| {
-                if !fixed && distance(v1, v2) == 1 {
-                    // println!("Fixing {:?} and {:?}", v1, v2);
-                    fixed = true;
-                    true
-                } else {
-                    false
-                }
-            };
-            if self.map[i] == self.map[i - 1] || can_fix(&self.map[i], &self.map[i - 1]) {
-                let steps = (self.map.len() - i - 1).min(i - 1);
-                for j in 1..=steps {
-                    if can_fix(&self.map[i + j], &self.map[i - j - 1]) {
-                        continue;
-                    }
-                    if self.map[i + j] != self.map[i - j - 1] {
-                        continue 'outer;
+    /// All reflection lines, which need exactly given number of smudges fixed
+    fn reflections(&self, smudges: usize) -> Vec<Reflection> {
+        let mut res = vec![];
+        for orientation in [Orientation::Horizontal, Orientation::Vertical] {
+            let lines = self.lines(orientation);
+            let len = lines.len();
+            'lines: for position in 1..len {
+                let steps = position.min(len - position);
+                let mut count = 0;
+                for j in 0..steps {
+                    let diff = lines[position - j - 1] ^ lines[position + j];
+                    count += diff.count_ones() as usize;
+                    if count > smudges {
+                        continue 'lines;
                     }
                 }
-                if fixed {
-                    return Some(i);
+                if count == smudges {
+                    let mut found = vec![];
+                    for j in 0..steps {
+                        let (a, b) = (position - j - 1, position + j);
+                        let mut diff = lines[a] ^ lines[b];
+                        while diff != 0 {
+                            let other = diff.trailing_zeros() as usize;
+                            diff &= diff - 1;
+                            found.push(match orientation {
+                                Orientation::Horizontal => [(a, other), (b, other)],
+                                Orientation::Vertical => [(other, a), (other, b)],
+                            });
+                        }
+                    }
+                    res.push(Reflection {
+                        orientation,
+                        position,
+                        smudges: found,
+                    });
                 }
             }
         }
-        None
+        res
     }
 }
 
-fn distance(v1: &Vec<Cell>, v2: &Vec<Cell>) -> usize {
-    v1.iter()
-        .zip(v2.iter())
-        .map(|(a, b)| if a == b { 0 } else { 1 })
-        .sum()
+impl Mirror {
+    /// Bits of a row (or column) of given length in reversed order
+    fn reversed(bits: u64, len: usize) -> u64 {
+        if len == 0 {
+            0
+        } else {
+            bits.reverse_bits() >> (64 - len)
+        }
+    }
+
+    /// Same after rotation by 180° around the center
+    fn point_symmetric(&self) -> bool {
+        (0..self.height)
+            .all(|r| self.rows[r] == Self::reversed(self.rows[self.height - 1 - r], self.width))
+    }
+
+    /// Same after rotation by 90° - row r of rotated pattern is reversed column r
+    fn rotation_symmetric(&self) -> bool {
+        self.width == self.height
+            && (0..self.height).all(|r| self.rows[r] == Self::reversed(self.cols[r], self.width))
+    }
+
+    /// Same after reflection along the diagonal from top left corner
+    fn diagonal_symmetric(&self) -> bool {
+        self.width == self.height && self.rows == self.cols
+    }
+
+    /// Same after reflection along the diagonal from top right corner
+    fn anti_diagonal_symmetric(&self) -> bool {
+        let n = self.width;
+        self.width == self.height
+            && (0..n).all(|r| self.rows[r] == Self::reversed(self.cols[n - 1 - r], n))
+    }
+
+    fn symmetries(&self) -> Symmetries {
+        Symmetries {
+            reflections: self.reflections(0),
+            point: self.point_symmetric(),
+            rotation: self.rotation_symmetric(),
+            diagonal: self.diagonal_symmetric(),
+            anti_diagonal: self.anti_diagonal_symmetric(),
+        }
+    }
 }
 
-pub fn thirteens_task_2(mut f: impl BufRead) -> u64 {
-    let mut nun_mirrors = 0;
-    let mut sum = 0;
-    loop {
-        if let Some(mirror) = Mirror::parse(&mut f) {
-            let mut has_split = false;
-            if let Some(split) = mirror.split() {
-                println!("Mirror {} horizontal split at {}", nun_mirrors, split);
-                sum += split as u64 * 100;
-                has_split = true;
-            }
+/// All symmetries of a pattern
+#[derive(Debug, PartialEq, Eq)]
+struct Symmetries {
+    /// Exact horizontal and vertical reflections
+    reflections: Vec<Reflection>,
+    /// 180° rotation
+    point: bool,
+    /// 90° rotation, only for square patterns
+    rotation: bool,
+    diagonal: bool,
+    anti_diagonal: bool,
+}
 
-            if let Some(split) = mirror.transpose().split() {
-                println!("Mirror {} vertical split at {}", nun_mirrors, split);
-                sum += split as u64;
-                has_split = true;
-            }
+impl Symmetries {
+    fn any(&self) -> bool {
+        !self.reflections.is_empty()
+            || self.point
+            || self.rotation
+            || self.diagonal
+            || self.anti_diagonal
+    }
+}
 
-            if !has_split {
-                panic!("No split found in mirror {}", nun_mirrors);
+impl Display for Symmetries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<String> = self
+            .reflections
+            .iter()
+            .map(|r| format!("{:?} reflection at {}", r.orientation, r.position))
+            .collect();
+        for (present, name) in [
+            (self.point, "180° rotation"),
+            (self.rotation, "90° rotation"),
+            (self.diagonal, "diagonal reflection"),
+            (self.anti_diagonal, "anti-diagonal reflection"),
+        ] {
+            if present {
+                names.push(name.to_string());
             }
-
-            nun_mirrors += 1;
+        }
+        if !self.any() {
+            write!(f, "no symmetry")
         } else {
-            break;
+            write!(f, "{}", names.join(", "))
         }
     }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Orientation {
+    /// Line between rows
+    Horizontal,
+    /// Line between columns
+    Vertical,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Reflection {
+    orientation: Orientation,
+    /// Number of rows (or columns) before the line
+    position: usize,
+    /// Pairs of mirrored cells (row, column), which differ - fixing either of them fixes the smudge
+    smudges: Vec<[(usize, usize); 2]>,
+}
+
+impl Reflection {
+    fn summary(&self) -> u64 {
+        match self.orientation {
+            Orientation::Horizontal => self.position as u64 * 100,
+            Orientation::Vertical => self.position as u64,
+        }
+    }
+}
+
+/// Sums reflection lines with given number of smudges in all mirrors
+fn summarize(mut f: impl BufRead, smudges: usize) -> u64 {
+    let mut num_mirrors = 0;
+    let mut sum = 0;
+    while let Some(mirror) = Mirror::parse(&mut f).unwrap() {
+        let reflections = mirror.reflections(smudges);
+        if reflections.is_empty() {
+            println!(
+                "Mirror {} has no reflection with {} smudges",
+                num_mirrors, smudges
+            );
+        }
+        for reflection in reflections {
+            println!("Mirror {} {:?}", num_mirrors, reflection);
+            sum += reflection.summary();
+        }
+        num_mirrors += 1;
+    }
     sum
 }
+
+/// Prints symmetries of every pattern, returns number of patterns with some symmetry
+pub fn thirteens_symmetries(mut f: impl BufRead) -> u64 {
+    let mut num_mirrors = 0;
+    let mut symmetric = 0;
+    while let Some(mirror) = Mirror::parse(&mut f).unwrap() {
+        let symmetries = mirror.symmetries();
+        println!("Mirror {}: {}", num_mirrors, symmetries);
+        if symmetries.any() {
+            symmetric += 1;
+        }
+        num_mirrors += 1;
+    }
+    symmetric
+}
+
+pub fn thirteens_task_1(f: impl BufRead) -> u64 {
+    summarize(f, 0)
+}
+
+pub fn thirteens_task_2(f: impl BufRead) -> u64 {
+    summarize(f, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::BufReader};
+
+    fn sample() -> BufReader<File> {
+        BufReader::new(File::open("data/input-day13-sample.txt").unwrap())
+    }
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(thirteens_task_1(sample()), 405);
+        assert_eq!(thirteens_task_2(sample()), 400);
+    }
+
+    #[test]
+    fn test_reflections() {
+        let mut f = sample();
+        let mirror = Mirror::parse(&mut f).unwrap().unwrap();
+        assert_eq!(
+            mirror.reflections(1),
+            vec![Reflection {
+                orientation: Orientation::Horizontal,
+                position: 3,
+                smudges: vec![[(0, 0), (5, 0)]],
+            }]
+        );
+        let mirror = Mirror::parse(&mut f).unwrap().unwrap();
+        let reflections = mirror.reflections(1);
+        assert_eq!(reflections.len(), 1);
+        assert_eq!(reflections[0].position, 1);
+        assert_eq!(reflections[0].smudges, vec![[(0, 4), (1, 4)]]);
+        // some lines need more fixes
+        assert!(!mirror.reflections(2).is_empty());
+        assert!(Mirror::parse(&mut f).unwrap().is_none());
+    }
+
+    /// Cell by cell search of reflections, as a reference for bitmasks
+    fn naive_reflections(pattern: &[Vec<char>], smudges: usize) -> Vec<(Orientation, usize)> {
+        let (height, width) = (pattern.len(), pattern[0].len());
+        let mut res = vec![];
+        for (orientation, len) in [
+            (Orientation::Horizontal, height),
+            (Orientation::Vertical, width),
+        ] {
+            for position in 1..len {
+                let mut count = 0;
+                for j in 0..position.min(len - position) {
+                    let (a, b) = (position - j - 1, position + j);
+                    count += match orientation {
+                        Orientation::Horizontal => (0..width)
+                            .filter(|c| pattern[a][*c] != pattern[b][*c])
+                            .count(),
+                        Orientation::Vertical => (0..height)
+                            .filter(|r| pattern[*r][a] != pattern[*r][b])
+                            .count(),
+                    };
+                }
+                if count == smudges {
+                    res.push((orientation, position));
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_bitmasks() {
+        let data = std::fs::read_to_string("data/input-day13.txt").unwrap();
+        let mut f = data.as_bytes();
+        for text in data.split("\n\n") {
+            let pattern: Vec<Vec<char>> = text.lines().map(|l| l.chars().collect()).collect();
+            let mirror = Mirror::parse(&mut f).unwrap().unwrap();
+            for smudges in 0..3 {
+                let found: Vec<_> = mirror
+                    .reflections(smudges)
+                    .iter()
+                    .map(|r| {
+                        assert_eq!(r.smudges.len(), smudges);
+                        (r.orientation, r.position)
+                    })
+                    .collect();
+                assert_eq!(found, naive_reflections(&pattern, smudges));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let parse = |s: String| Mirror::parse(&mut s.as_bytes());
+        assert!(parse("#".repeat(64)).unwrap().is_some());
+        assert!(parse("#".repeat(65)).is_err());
+        assert!(parse("#.\n".repeat(65)).is_err());
+        assert!(parse("#.\n#\n".to_string()).is_err());
+        assert!(parse("#x\n".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_symmetries() {
+        let parse = |s: &str| Mirror::parse(&mut s.as_bytes()).unwrap().unwrap();
+        // pinwheel - symmetric by 90° rotation, but not by reflections
+        let pinwheel = parse(".#..\n...#\n#...\n..#.\n");
+        let symmetries = pinwheel.symmetries();
+        assert!(symmetries.point && symmetries.rotation);
+        assert!(!symmetries.diagonal && !symmetries.anti_diagonal);
+        assert!(symmetries.reflections.is_empty());
+        assert_eq!(symmetries.to_string(), "180° rotation, 90° rotation");
+
+        // main diagonal is symmetric along the other diagonal too
+        let diagonal = parse("#..\n.#.\n..#\n");
+        let symmetries = diagonal.symmetries();
+        assert!(symmetries.diagonal && symmetries.anti_diagonal && symmetries.point);
+        assert!(!symmetries.rotation);
+
+        let anti = parse("##.\n#..\n...\n");
+        let symmetries = anti.symmetries();
+        assert!(symmetries.diagonal && !symmetries.anti_diagonal);
+        let anti = parse(".##\n..#\n...\n");
+        assert!(anti.anti_diagonal_symmetric());
+        assert!(!anti.point_symmetric());
+
+        // not square, but symmetric by 180°
+        let point = parse("#..\n..#\n");
+        let symmetries = point.symmetries();
+        assert!(symmetries.point && !symmetries.rotation && !symmetries.diagonal);
+
+        let none = parse("##.\n#..\n");
+        assert_eq!(none.symmetries().to_string(), "no symmetry");
+        assert_eq!(thirteens_symmetries(sample()), 2);
+    }
+}
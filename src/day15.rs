@@ -1,18 +1,81 @@
-use std::{collections::HashMap, io::BufRead, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Write},
+    rc::Rc,
+    str::FromStr,
+};
 
+use crate::rng::Rng;
 use anyhow::Context;
 
 fn hash(s: &str) -> u32 {
     s.chars().fold(0, |acc, c| ((acc + c as u32) * 17) % 256)
 }
 
+/// Hash function used to pick a box for label, result is taken modulo number of boxes
+pub type Hasher = fn(&str) -> u64;
+
+/// HASH algorithm from the puzzle
+pub fn puzzle_hash(s: &str) -> u64 {
+    hash(s) as u64
+}
+
+/// 64 bit FNV-1a
+pub fn fnv1a_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |acc, b| {
+        (acc ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Bernstein's djb2
+pub fn djb2_hash(s: &str) -> u64 {
+    s.bytes().fold(5381u64, |acc, b| {
+        acc.wrapping_mul(33).wrapping_add(b as u64)
+    })
+}
+
+pub fn named_hasher(name: &str) -> Option<Hasher> {
+    match name {
+        "puzzle" => Some(puzzle_hash),
+        "fnv1a" => Some(fnv1a_hash),
+        "djb2" => Some(djb2_hash),
+        _ => None,
+    }
+}
+
+/// Box of lenses, which keeps order in which lenses were added
+trait LensBox {
+    fn new() -> Self;
+    fn add_len(&mut self, label: String, focal_value: u8);
+    fn remove_len(&mut self, label: &str);
+    /// Lenses in order
+    fn lenses(&self) -> Box<dyn Iterator<Item = (&str, u8)> + '_>;
+
+    fn len(&self) -> usize {
+        self.lenses().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lenses().next().is_none()
+    }
+
+    fn focusing_strength(&self) -> u64 {
+        self.lenses()
+            .enumerate()
+            .map(|(pos, (_, value))| value as u64 * (pos as u64 + 1))
+            .sum()
+    }
+}
+
+/// Original box - removal has to search the order vector
 #[derive(Debug)]
-struct Carton {
+struct VecCarton {
     lens: Vec<String>,
     lens_values: HashMap<String, u8>,
 }
 
-impl Carton {
+impl LensBox for VecCarton {
     fn new() -> Self {
         Self {
             lens: Vec::new(),
@@ -35,12 +98,115 @@ impl Carton {
         }
     }
 
-    fn focusing_strength(&self) -> u32 {
-        self.lens
-            .iter()
-            .enumerate()
-            .map(|(pos, l)| *self.lens_values.get(l).unwrap() as u32 * (pos as u32 + 1))
-            .sum()
+    fn lenses(&self) -> Box<dyn Iterator<Item = (&str, u8)> + '_> {
+        Box::new(
+            self.lens
+                .iter()
+                .map(|l| (l.as_str(), *self.lens_values.get(l).unwrap())),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    label: Rc<str>,
+    focal_value: u8,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Box with lenses in a slab linked in insertion order, index points from label to slot,
+/// so insert, update and remove are all O(1)
+#[derive(Debug)]
+struct Carton {
+    slots: Vec<Slot>,
+    /// Slots of removed lenses, which can be reused
+    free: Vec<usize>,
+    index: HashMap<Rc<str>, usize>,
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+impl LensBox for Carton {
+    fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn add_len(&mut self, label: String, focal_value: u8) {
+        if let Some(&i) = self.index.get(label.as_str()) {
+            self.slots[i].focal_value = focal_value;
+            return;
+        }
+        let label: Rc<str> = label.into();
+        let slot = Slot {
+            label: label.clone(),
+            focal_value,
+            prev: self.tail,
+            next: None,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.slots[i] = slot;
+                i
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        match self.tail {
+            Some(t) => self.slots[t].next = Some(i),
+            None => self.head = Some(i),
+        }
+        self.tail = Some(i);
+        self.index.insert(label, i);
+    }
+
+    fn remove_len(&mut self, label: &str) {
+        if let Some(i) = self.index.remove(label) {
+            let (prev, next) = (self.slots[i].prev, self.slots[i].next);
+            match prev {
+                Some(p) => self.slots[p].next = next,
+                None => self.head = next,
+            }
+            match next {
+                Some(n) => self.slots[n].prev = prev,
+                None => self.tail = prev,
+            }
+            self.free.push(i);
+        }
+    }
+
+    fn lenses(&self) -> Box<dyn Iterator<Item = (&str, u8)> + '_> {
+        let mut current = self.head;
+        Box::new(std::iter::from_fn(move || {
+            let slot = &self.slots[current?];
+            current = slot.next;
+            Some((&*slot.label, slot.focal_value))
+        }))
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+struct Lenses<'a, C>(&'a C);
+
+impl<C: LensBox> Display for Lenses<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lens: Vec<String> = self
+            .0
+            .lenses()
+            .map(|(label, value)| format!("[{} {}]", label, value))
+            .collect();
+        write!(f, "{}", lens.join(" "))
     }
 }
 
@@ -49,6 +215,15 @@ enum Operation {
     Remove(String),
 }
 
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Add(label, value) => write!(f, "{}={}", label, value),
+            Operation::Remove(label) => write!(f, "{}-", label),
+        }
+    }
+}
+
 impl FromStr for Operation {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -64,49 +239,219 @@ impl FromStr for Operation {
 }
 
 #[derive(Debug)]
-struct Line {
-    cartons: Vec<Carton>,
+struct Line<C = Carton> {
+    cartons: Vec<C>,
+    hasher: Hasher,
 }
 
-impl Line {
+impl<C: LensBox> Line<C> {
+    /// Line from the puzzle - 256 boxes and HASH algorithm
     fn new() -> Self {
+        Self::with_hasher(puzzle_hash, 256)
+    }
+
+    fn with_hasher(hasher: Hasher, buckets: usize) -> Self {
+        assert!(buckets > 0, "Line needs at least one box");
         let mut cartons = Vec::new();
-        for _ in 0..256 {
-            cartons.push(Carton::new());
+        for _ in 0..buckets {
+            cartons.push(C::new());
         }
-        Self { cartons }
+        Self { cartons, hasher }
+    }
+
+    fn position(&self, label: &str) -> usize {
+        ((self.hasher)(label) % self.cartons.len() as u64) as usize
     }
 
     fn apply_operation(&mut self, operation: Operation) {
         match operation {
             Operation::Add(label, focus_value) => {
-                let pos = hash(&label) as usize;
+                let pos = self.position(&label);
                 self.cartons[pos].add_len(label, focus_value);
             }
             Operation::Remove(label) => {
-                let pos = hash(&label) as usize;
+                let pos = self.position(&label);
                 self.cartons[pos].remove_len(&label);
             }
         }
     }
 
-    fn focusing_strength(&self) -> u32 {
+    fn stats(&self) -> Stats {
+        let mut histogram = vec![];
+        for carton in &self.cartons {
+            let len = carton.len();
+            if histogram.len() <= len {
+                histogram.resize(len + 1, 0);
+            }
+            histogram[len] += 1;
+        }
+        let lenses = self.cartons.iter().map(|c| c.len()).sum::<usize>();
+        let occupied = self.cartons.len() - histogram[0];
+        Stats {
+            buckets: self.cartons.len(),
+            lenses,
+            occupied,
+            max_chain: histogram.len() - 1,
+            collisions: lenses - occupied,
+            histogram,
+        }
+    }
+
+    fn focusing_strength(&self) -> u64 {
         self.cartons
             .iter()
             .enumerate()
-            .map(|(idx, c)| c.focusing_strength() * (idx as u32 + 1))
+            .map(|(idx, c)| c.focusing_strength() * (idx as u64 + 1))
             .sum()
     }
 }
 
+impl<C: LensBox> Display for Line<C> {
+    /// Non empty boxes in the same format as in the puzzle
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, carton) in self.cartons.iter().enumerate() {
+            if !carton.is_empty() {
+                writeln!(f, "Box {}: {}", idx, Lenses(carton))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Distribution of lenses in boxes
+#[derive(Debug, PartialEq)]
+struct Stats {
+    buckets: usize,
+    lenses: usize,
+    /// Number of non empty boxes
+    occupied: usize,
+    /// Number of boxes for each count of lenses
+    histogram: Vec<usize>,
+    max_chain: usize,
+    /// Lenses, which had to share box with another lens
+    collisions: usize,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} lenses in {} of {} boxes, load factor {:.2}, max chain {}, collisions {}",
+            self.lenses,
+            self.occupied,
+            self.buckets,
+            self.lenses as f64 / self.buckets as f64,
+            self.max_chain,
+            self.collisions
+        )?;
+        let widest = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (len, &count) in self.histogram.iter().enumerate() {
+            let bar = "#".repeat((count * 50).div_ceil(widest));
+            writeln!(f, "{:>3} lenses: {:>6} {}", len, count, bar)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads operations (one or more comma separated on a line) and after each one prints
+/// non empty boxes and focusing power. Returns final focusing power.
+pub fn repl(input: impl BufRead, mut out: impl Write) -> Result<u64, anyhow::Error> {
+    let mut line: Line = Line::new();
+    for input_line in input.lines() {
+        let input_line = input_line?;
+        for command in input_line
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+        {
+            let operation = match command.parse::<Operation>() {
+                Ok(operation) => operation,
+                Err(e) => {
+                    writeln!(out, "Invalid command {}: {}", command, e)?;
+                    continue;
+                }
+            };
+            writeln!(out, "After \"{}\":", operation)?;
+            line.apply_operation(operation);
+            write!(out, "{}", line)?;
+            writeln!(out, "Focusing power: {}\n", line.focusing_strength())?;
+        }
+        out.flush()?;
+    }
+    Ok(line.focusing_strength())
+}
+
+/// Like task 2, but prints state after every step
+pub fn fifteenth_steps(f: impl BufRead) -> u64 {
+    repl(f, std::io::stdout()).unwrap()
+}
+
 pub fn fifteenth_task_2(f: impl BufRead) -> u64 {
-    let mut line = Line::new();
+    let mut line: Line = Line::new();
     let s = f.lines().next().unwrap().unwrap();
     s.split(',')
         .map(|s| s.parse::<Operation>().unwrap())
         .for_each(|op| line.apply_operation(op));
-    println!("line: {:?}", line);
-    line.focusing_strength() as u64
+    println!("line:\n{}", line);
+    line.focusing_strength()
+}
+
+/// Statistics of boxes after all operations for each hash function and bucket count
+pub fn fifteenth_stats(f: impl BufRead) -> u64 {
+    let s = f.lines().next().unwrap().unwrap();
+    let mut max_chain = 0;
+    for name in ["puzzle", "fnv1a", "djb2"] {
+        for buckets in [256, 1024] {
+            let mut line: Line = Line::with_hasher(named_hasher(name).unwrap(), buckets);
+            s.split(',')
+                .map(|s| s.parse::<Operation>().unwrap())
+                .for_each(|op| line.apply_operation(op));
+            let stats = line.stats();
+            println!("{} hash, {} boxes:\n{}", name, buckets, stats);
+            max_chain = max_chain.max(stats.max_chain);
+        }
+    }
+    max_chain as u64
+}
+
+/// Random operations over given number of distinct labels, roughly one third are removals
+fn generate_operations(count: usize, labels: usize, seed: u64) -> Vec<Operation> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| {
+            let label = format!("l{}", rng.below(labels as u64));
+            if rng.below(3) == 0 {
+                Operation::Remove(label)
+            } else {
+                Operation::Add(label, rng.below(9) as u8 + 1)
+            }
+        })
+        .collect()
+}
+
+fn run_operations<C: LensBox>(operations: &[Operation]) -> (u64, std::time::Duration) {
+    let start = std::time::Instant::now();
+    let mut line: Line<C> = Line::new();
+    for op in operations {
+        let op = match op {
+            Operation::Add(label, value) => Operation::Add(label.clone(), *value),
+            Operation::Remove(label) => Operation::Remove(label.clone()),
+        };
+        line.apply_operation(op);
+    }
+    (line.focusing_strength(), start.elapsed())
+}
+
+/// Compares vector based boxes with linked slab boxes on generated operations
+pub fn benchmark(count: usize, labels: usize) {
+    let operations = generate_operations(count, labels, 42);
+    let (vec_power, vec_time) = run_operations::<VecCarton>(&operations);
+    let (slab_power, slab_time) = run_operations::<Carton>(&operations);
+    assert_eq!(vec_power, slab_power, "Box implementations differ");
+    println!(
+        "{} operations over {} labels: focusing power {}, vector {:?}, linked slab {:?}",
+        count, labels, slab_power, vec_time, slab_time
+    );
 }
 
 #[cfg(test)]
@@ -117,4 +462,90 @@ mod tests {
     fn test_hash() {
         assert_eq!(hash("HASH"), 52);
     }
+
+    #[test]
+    fn test_carton() {
+        let mut carton = Carton::new();
+        carton.add_len("a".to_string(), 1);
+        carton.add_len("b".to_string(), 2);
+        carton.add_len("c".to_string(), 3);
+        carton.add_len("a".to_string(), 4);
+        carton.remove_len("b");
+        carton.remove_len("x");
+        carton.add_len("d".to_string(), 5);
+        let lenses: Vec<_> = carton.lenses().collect();
+        assert_eq!(lenses, vec![("a", 4), ("c", 3), ("d", 5)]);
+        // freed slot is reused
+        assert_eq!(carton.slots.len(), 3);
+        carton.remove_len("a");
+        carton.remove_len("d");
+        assert_eq!(carton.focusing_strength(), 3);
+        carton.remove_len("c");
+        assert!(carton.is_empty());
+    }
+
+    #[test]
+    fn test_implementations_agree() {
+        for labels in [5, 50, 500] {
+            let operations = generate_operations(5000, labels, labels as u64);
+            assert_eq!(
+                run_operations::<VecCarton>(&operations).0,
+                run_operations::<Carton>(&operations).0
+            );
+        }
+        let data = std::fs::read_to_string("data/input-day15.txt").unwrap();
+        let operations: Vec<Operation> =
+            data.trim().split(',').map(|s| s.parse().unwrap()).collect();
+        assert_eq!(
+            run_operations::<VecCarton>(&operations).0,
+            run_operations::<Carton>(&operations).0
+        );
+    }
+
+    #[test]
+    fn test_hashers() {
+        let ops = "rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7";
+        let fill = |line: &mut Line| {
+            ops.split(',')
+                .for_each(|op| line.apply_operation(op.parse().unwrap()))
+        };
+        let mut line: Line = Line::new();
+        fill(&mut line);
+        assert_eq!(
+            line.stats(),
+            Stats {
+                buckets: 256,
+                lenses: 5,
+                occupied: 2,
+                histogram: vec![254, 0, 1, 1],
+                max_chain: 3,
+                collisions: 3
+            }
+        );
+        // puzzle hash is below 256, so it spreads only over first 256 of 1024 boxes
+        let mut line: Line = Line::with_hasher(puzzle_hash, 1024);
+        fill(&mut line);
+        assert_eq!(line.focusing_strength(), 145);
+        let mut line: Line = Line::with_hasher(fnv1a_hash, 1);
+        fill(&mut line);
+        assert_eq!(line.stats().max_chain, 5);
+        // rn, cm, ot, ab, pc
+        assert_eq!(line.focusing_strength(), 1 + 2 * 2 + 7 * 3 + 5 * 4 + 6 * 5);
+        assert_eq!(fnv1a_hash(""), 0xcbf29ce484222325);
+        assert_eq!(djb2_hash("a"), 5381 * 33 + 97);
+        assert!(named_hasher("md5").is_none());
+    }
+
+    #[test]
+    fn test_repl() {
+        let input = "rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7\nab-\nbad";
+        let mut out = vec![];
+        let power = repl(input.as_bytes(), &mut out).unwrap();
+        assert_eq!(power, 81);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("After \"rn=1\":\nBox 0: [rn 1]\nFocusing power: 1\n\n"));
+        assert!(out.contains("After \"ot=7\":\nBox 0: [rn 1] [cm 2]\nBox 3: [ot 7] [ab 5] [pc 6]\nFocusing power: 145\n"));
+        assert!(out.contains("Box 3: [ot 7] [pc 6]\n"));
+        assert!(out.contains("Invalid command bad"));
+    }
 }
@@ -1,5 +1,10 @@
-use anyhow::Context;
-use std::{io::BufRead, str::FromStr};
+use crate::geometry::{interior_points, perimeter, self_intersections};
+use anyhow::{bail, Context};
+use std::{
+    fmt::Display,
+    io::{BufRead, Write},
+    str::FromStr,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 enum Direction {
@@ -31,11 +36,63 @@ impl FromStr for Direction {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "2" => Ok(Direction::Left),
+            "L" => Ok(Direction::Left),
+            "R" => Ok(Direction::Right),
+            "U" => Ok(Direction::Up),
+            "D" => Ok(Direction::Down),
+            _ => anyhow::bail!("Unknown direction: {}", s),
+        }
+    }
+}
+
+impl Direction {
+    fn letter(&self) -> char {
+        match self {
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+        }
+    }
+
+    /// Direction from digit in hex encoding
+    fn from_code(code: &str) -> Result<Self, anyhow::Error> {
+        match code {
             "0" => Ok(Direction::Right),
-            "3" => Ok(Direction::Up),
             "1" => Ok(Direction::Down),
-            _ => anyhow::bail!("Unknown direction: {}", s),
+            "2" => Ok(Direction::Left),
+            "3" => Ok(Direction::Up),
+            _ => anyhow::bail!("Unknown direction code: {}", code),
+        }
+    }
+
+    /// Digit used for direction in hex encoding
+    fn code(&self) -> u8 {
+        match self {
+            Direction::Right => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Up => 3,
+        }
+    }
+}
+
+/// How dig plan line encodes the move
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    /// Direction letter and steps are the move, hex field is a color (part 1)
+    Literal,
+    /// Hex field holds 5 digits of steps and direction code, first two fields are ignored (part 2)
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "literal" => Ok(Encoding::Literal),
+            "hex" => Ok(Encoding::Hex),
+            _ => anyhow::bail!("Unknown encoding: {}", s),
         }
     }
 }
@@ -44,71 +101,463 @@ impl FromStr for Direction {
 struct Instruction {
     direction: Direction,
     steps: i64,
+    /// Hex field as #rrggbb
     color: String,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 enum Cell {
-    Trench,
+    /// Trench dug by instruction with given color
+    Trench(Rgb),
     Basin,
 }
 
-impl FromStr for Instruction {
-    type Err = anyhow::Error;
+type Rgb = (u8, u8, u8);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(' ');
-        let _direction = parts.next().context("No direction")?;
-        let _steps = parts.next().context("No steps")?;
-        let color = parts.next().context("No color")?.to_string();
-        let direction: Direction = (&color[7..8]).parse()?;
-        let steps = i64::from_str_radix(&color[2..7], 16)?;
+/// Maximum number of cells in rasterized lagoon, bigger plans are only measured
+const MAX_RASTER_CELLS: i64 = 10_000_000;
+
+impl Instruction {
+    fn parse(s: &str, encoding: Encoding) -> Result<Self, anyhow::Error> {
+        let mut parts = s.split_whitespace();
+        let direction = parts.next().context("No direction")?;
+        let steps = parts.next().context("No steps")?;
+        let color = parts.next().context("No color")?;
+        let color = color
+            .strip_prefix('(')
+            .and_then(|c| c.strip_suffix(')'))
+            .filter(|c| {
+                c.len() == 7 && c.starts_with('#') && c[1..].bytes().all(|b| b.is_ascii_hexdigit())
+            })
+            .with_context(|| format!("Invalid color field {}", color))?
+            .to_string();
+        let (direction, steps) = match encoding {
+            Encoding::Literal => (
+                direction.parse()?,
+                i64::try_from(
+                    steps
+                        .parse::<u64>()
+                        .with_context(|| format!("Invalid steps {}", steps))?,
+                )?,
+            ),
+            Encoding::Hex => (
+                Direction::from_code(&color[6..7])?,
+                i64::from_str_radix(&color[1..6], 16)?,
+            ),
+        };
         Ok(Self {
             direction,
             steps,
             color,
         })
     }
+
+    fn rgb(&self) -> Result<Rgb, anyhow::Error> {
+        let channel = |i: usize| u8::from_str_radix(&self.color[i..i + 2], 16);
+        Ok((channel(1)?, channel(3)?, channel(5)?))
+    }
+
+    /// Hex field encoding this instruction's move, steps must fit in 5 hex digits
+    fn hex_code(&self) -> Result<String, anyhow::Error> {
+        if !(0..=0xfffff).contains(&self.steps) {
+            bail!("{} steps cannot be encoded in 5 hex digits", self.steps);
+        }
+        Ok(format!("#{:05x}{}", self.steps, self.direction.code()))
+    }
+
+    /// Writes instruction as a line, which parses back to the same move in given encoding.
+    /// Literal encoding keeps the color, hex encoding replaces it by the move code,
+    /// which is the same for instructions parsed from hex encoding, so converting hex plan
+    /// to literal and back gives the original plan.
+    /// The line also repeats the move literally, so it is valid in both encodings.
+    fn to_line(&self, encoding: Encoding) -> Result<String, anyhow::Error> {
+        let color = match encoding {
+            Encoding::Literal => self.color.clone(),
+            Encoding::Hex => self.hex_code()?,
+        };
+        Ok(format!(
+            "{} {} ({})",
+            self.direction.letter(),
+            self.steps,
+            color
+        ))
+    }
 }
 
-pub fn area(poly: &Vec<(i64, i64)>) -> i64 {
-    let mut sum = 0;
-    for i in 0..poly.len() - 1 {
-        // sum += (poly[i].0 + poly[i + 1].0) * (poly[i].1 - poly[i + 1].1)
-        sum += poly[i].1 * poly[i + 1].0 - poly[i + 1].1 * poly[i].0
+fn parse_plan(f: impl BufRead, encoding: Encoding) -> Result<Vec<Instruction>, anyhow::Error> {
+    f.lines()
+        .enumerate()
+        .map(|(no, l)| {
+            Instruction::parse(&l?, encoding).with_context(|| format!("Line {}", no + 1))
+        })
+        .collect()
+}
+
+/// Rewrites dig plan from one encoding to another
+pub fn convert(
+    f: impl BufRead,
+    mut out: impl Write,
+    from: Encoding,
+    to: Encoding,
+) -> Result<(), anyhow::Error> {
+    for (no, instruction) in parse_plan(f, from)?.iter().enumerate() {
+        let line = instruction
+            .to_line(to)
+            .with_context(|| format!("Line {}", no + 1))?;
+        writeln!(out, "{}", line)?;
     }
-    sum.abs() / 2
+    Ok(())
 }
 
-pub fn eighteens_task_1(f: impl BufRead) -> u64 {
-    let instructions = f
-        .lines()
-        .map(|l| l.unwrap().parse::<Instruction>().unwrap())
-        .collect::<Vec<_>>();
-    // println!("Instructions: {:?}", instructions);
+fn polygon(instructions: &[Instruction]) -> Vec<(i64, i64)> {
     let (mut row, mut col) = (0, 0);
     let mut poly = vec![(row, col)];
-    for instruction in &instructions {
+    for instruction in instructions {
         (row, col) = instruction.direction.jump((row, col), instruction.steps);
         poly.push((row, col));
     }
+    poly
+}
 
-    println!("Poly {:?}", poly);
+/// Problem in dig plan, instructions are indexed from 0
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum PlanIssue {
+    ZeroLength(usize),
+    NotClosed { end: (i64, i64) },
+    Intersection(usize, usize),
+}
 
-    let area = area(&poly);
-    let cir: i64 = instructions.iter().map(|i| i.steps).sum();
+impl Display for PlanIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanIssue::ZeroLength(i) => write!(f, "instruction {} does not move", i),
+            PlanIssue::NotClosed { end } => {
+                write!(f, "plan ends at {:?} instead of returning to start", end)
+            }
+            PlanIssue::Intersection(i, j) => {
+                write!(
+                    f,
+                    "trench of instructions {} and {} crosses or overlaps",
+                    i, j
+                )
+            }
+        }
+    }
+}
 
-    (area + cir / 2 + 1) as u64
+/// Checks that plan is a simple closed polygon
+fn validate_plan(instructions: &[Instruction]) -> Vec<PlanIssue> {
+    let mut issues = vec![];
+    // edges of polygon without zero moves, mapped back to instruction indexes
+    let mut indexes = vec![];
+    let mut moves = vec![];
+    for (i, instruction) in instructions.iter().enumerate() {
+        if instruction.steps == 0 {
+            issues.push(PlanIssue::ZeroLength(i));
+        } else {
+            indexes.push(i);
+            moves.push(instruction.clone());
+        }
+    }
+    let poly = polygon(&moves);
+    let end = *poly.last().unwrap();
+    if end != (0, 0) {
+        issues.push(PlanIssue::NotClosed { end });
+    }
+    issues.extend(
+        self_intersections(&poly)
+            .into_iter()
+            // implied closing edge of not closed plan is not an instruction
+            .filter(|(_, j)| *j < indexes.len())
+            .map(|(i, j)| PlanIssue::Intersection(indexes[i], indexes[j])),
+    );
+    issues
+}
+
+fn lagoon_size(instructions: &[Instruction]) -> Result<u64, anyhow::Error> {
+    let issues = validate_plan(instructions);
+    if !issues.is_empty() {
+        let issues: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+        bail!("Invalid dig plan: {}", issues.join(", "));
+    }
+    let poly = polygon(instructions);
+    // trench cells are lattice points inside and on the boundary of the polygon
+    Ok((interior_points(&poly) + perimeter(&poly)) as u64)
+}
+
+/// Lagoon dug into a grid, None is untouched ground
+#[derive(Debug)]
+struct Lagoon {
+    grid: Vec<Vec<Option<Cell>>>,
+}
+
+impl Lagoon {
+    /// Digs trench and fills its interior, None if plan is too big for a grid
+    fn dig(instructions: &[Instruction]) -> Result<Option<Self>, anyhow::Error> {
+        let poly = polygon(instructions);
+        let min_row = poly.iter().map(|p| p.0).min().unwrap_or(0);
+        let max_row = poly.iter().map(|p| p.0).max().unwrap_or(0);
+        let min_col = poly.iter().map(|p| p.1).min().unwrap_or(0);
+        let max_col = poly.iter().map(|p| p.1).max().unwrap_or(0);
+        // one cell of ground around, so that outside is connected
+        let height = max_row - min_row + 3;
+        let width = max_col - min_col + 3;
+        if height * width > MAX_RASTER_CELLS {
+            return Ok(None);
+        }
+
+        let mut grid = vec![vec![None; width as usize]; height as usize];
+        let (mut row, mut col) = (1 - min_row, 1 - min_col);
+        // start hole is dug even before any move, closed plan digs it again by its last move
+        let start_color = match instructions.last() {
+            Some(instruction) => instruction.rgb()?,
+            None => (0, 0, 0),
+        };
+        grid[row as usize][col as usize] = Some(Cell::Trench(start_color));
+        for instruction in instructions {
+            let color = instruction.rgb()?;
+            for _ in 0..instruction.steps {
+                (row, col) = instruction.direction.jump((row, col), 1);
+                grid[row as usize][col as usize] = Some(Cell::Trench(color));
+            }
+        }
+
+        // flood fill ground from the corner, what remains untouched is the basin
+        let mut outside = vec![vec![false; width as usize]; height as usize];
+        let mut stack = vec![(0, 0)];
+        outside[0][0] = true;
+        while let Some(pos) = stack.pop() {
+            for dir in [
+                Direction::Left,
+                Direction::Right,
+                Direction::Up,
+                Direction::Down,
+            ] {
+                let (r, c) = dir.jump(pos, 1);
+                if r < 0 || c < 0 || r >= height || c >= width {
+                    continue;
+                }
+                let (r, c) = (r as usize, c as usize);
+                if !outside[r][c] && grid[r][c].is_none() {
+                    outside[r][c] = true;
+                    stack.push((r as i64, c as i64));
+                }
+            }
+        }
+        for (r, row) in grid.iter_mut().enumerate() {
+            for (c, cell) in row.iter_mut().enumerate() {
+                if cell.is_none() && !outside[r][c] {
+                    *cell = Some(Cell::Basin);
+                }
+            }
+        }
+
+        Ok(Some(Lagoon { grid }))
+    }
+
+    fn size(&self) -> u64 {
+        self.grid
+            .iter()
+            .map(|row| row.iter().filter(|c| c.is_some()).count() as u64)
+            .sum()
+    }
+
+    /// Writes lagoon as binary PPM image, trench in its colors, basin dark gray, ground white
+    fn write_ppm(&self, mut out: impl Write) -> Result<(), anyhow::Error> {
+        let height = self.grid.len();
+        let width = self.grid.first().map(|r| r.len()).unwrap_or(0);
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        for row in &self.grid {
+            for cell in row {
+                let (r, g, b) = match cell {
+                    Some(Cell::Trench(color)) => *color,
+                    Some(Cell::Basin) => (64, 64, 64),
+                    None => (255, 255, 255),
+                };
+                out.write_all(&[r, g, b])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn eighteens_task_1(f: impl BufRead) -> u64 {
+    let instructions = parse_plan(f, Encoding::Literal).unwrap();
+    lagoon_size(&instructions).unwrap()
+}
+
+/// Digs lagoon from part 1 plan and writes it as PPM image, returns lagoon size
+pub fn eighteens_render(f: impl BufRead, out: impl Write) -> Result<u64, anyhow::Error> {
+    let instructions = parse_plan(f, Encoding::Literal)?;
+    let size = lagoon_size(&instructions)?;
+    Lagoon::dig(&instructions)?
+        .context("Plan is too big to be drawn")?
+        .write_ppm(out)?;
+    Ok(size)
+}
+
+pub fn eighteens_task_2(f: impl BufRead) -> u64 {
+    let instructions = parse_plan(f, Encoding::Hex).unwrap();
+    lagoon_size(&instructions).unwrap()
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::geometry::area;
+    use std::{fs::File, io::BufReader};
     #[test]
     fn test_area() {
         let p = vec![(0, 0), (0, 1), (1, 1), (1, 0), (0, 0)];
         let a = area(&p);
         assert_eq!(1, a);
     }
+
+    #[test]
+    fn test_lagoon() {
+        let f = BufReader::new(File::open("data/input-day18-sample.txt").unwrap());
+        assert_eq!(eighteens_task_1(f), 62);
+        let f = BufReader::new(File::open("data/input-day18-sample.txt").unwrap());
+        assert_eq!(eighteens_task_2(f), 952408144115);
+    }
+
+    #[test]
+    fn test_dig() {
+        let f = BufReader::new(File::open("data/input-day18-sample.txt").unwrap());
+        let instructions = parse_plan(f, Encoding::Literal).unwrap();
+        let lagoon = Lagoon::dig(&instructions).unwrap().unwrap();
+        assert_eq!(lagoon.size(), 62);
+        assert_eq!(lagoon.grid[1][2], Some(Cell::Trench((0x70, 0xc7, 0x10))));
+        // start is dug by the last instruction closing the loop
+        assert_eq!(lagoon.grid[1][1], Some(Cell::Trench((0x7a, 0x21, 0xe3))));
+        assert_eq!(lagoon.grid[2][2], Some(Cell::Basin));
+
+        let mut ppm = vec![];
+        lagoon.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n9 12\n255\n"));
+        assert_eq!(ppm.len(), 12 + 9 * 12 * 3);
+
+        let f = BufReader::new(File::open("data/input-day18-sample.txt").unwrap());
+        let instructions = parse_plan(f, Encoding::Hex).unwrap();
+        assert!(Lagoon::dig(&instructions).unwrap().is_none());
+
+        // only the start hole, the same as in lagoon_size
+        let lagoon = Lagoon::dig(&[]).unwrap().unwrap();
+        assert_eq!(lagoon.size(), 1);
+        assert_eq!(lagoon_size(&[]).unwrap(), 1);
+
+        let f = BufReader::new(File::open("data/input-day18-sample.txt").unwrap());
+        let mut rendered = vec![];
+        assert_eq!(eighteens_render(f, &mut rendered).unwrap(), 62);
+        assert_eq!(rendered, ppm);
+    }
+
+    #[test]
+    fn test_validation() {
+        let plan = |s: &str| parse_plan(s.as_bytes(), Encoding::Literal).unwrap();
+        let ok = plan("R 2 (#000000)\nD 2 (#000000)\nL 2 (#000000)\nU 2 (#000000)");
+        assert!(validate_plan(&ok).is_empty());
+        assert_eq!(lagoon_size(&ok).unwrap(), 9);
+
+        let open = plan("R 2 (#000000)\nD 2 (#000000)\nL 0 (#000000)\nL 1 (#000000)");
+        assert_eq!(
+            validate_plan(&open),
+            vec![
+                PlanIssue::ZeroLength(2),
+                PlanIssue::NotClosed { end: (2, 1) }
+            ]
+        );
+
+        let crossing = plan(
+            "R 2 (#000000)\nD 2 (#000000)\nL 1 (#000000)\nU 3 (#000000)\nL 1 (#000000)\nD 1 (#000000)",
+        );
+        assert_eq!(
+            validate_plan(&crossing),
+            vec![PlanIssue::Intersection(0, 3)]
+        );
+
+        let back =
+            plan("R 2 (#000000)\nL 1 (#000000)\nD 1 (#000000)\nL 1 (#000000)\nU 1 (#000000)");
+        assert_eq!(
+            validate_plan(&back),
+            vec![PlanIssue::Intersection(0, 1), PlanIssue::Intersection(0, 2)]
+        );
+        assert!(lagoon_size(&back).is_err());
+
+        // moves backwards are rejected when parsing, not reported as zero length
+        assert!(parse_plan(
+            "R 2 (#000000)\nR -2 (#000000)".as_bytes(),
+            Encoding::Literal
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = std::fs::read_to_string("data/input-day18-sample.txt").unwrap();
+        let mut literal = vec![];
+        convert(
+            original.as_bytes(),
+            &mut literal,
+            Encoding::Hex,
+            Encoding::Literal,
+        )
+        .unwrap();
+        assert!(String::from_utf8_lossy(&literal).starts_with("R 461937 (#70c710)\n"));
+        let mut hex = vec![];
+        convert(&literal[..], &mut hex, Encoding::Literal, Encoding::Hex).unwrap();
+        assert_eq!(
+            parse_plan(original.as_bytes(), Encoding::Hex).unwrap(),
+            parse_plan(&hex[..], Encoding::Hex).unwrap()
+        );
+
+        // part 1 plan converted to hex encoding keeps the moves
+        let mut converted = vec![];
+        convert(
+            original.as_bytes(),
+            &mut converted,
+            Encoding::Literal,
+            Encoding::Hex,
+        )
+        .unwrap();
+        let converted = String::from_utf8(converted).unwrap();
+        assert_eq!(
+            converted,
+            std::fs::read_to_string("data/input-day18-sample2.txt").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_instructions() {
+        let parse = |s: &str, encoding| Instruction::parse(s, encoding);
+        assert!(parse("R -2 (#000000)", Encoding::Literal).is_err());
+        assert!(parse("R 2 (#ééé)", Encoding::Literal).is_err());
+        assert!(parse("R 2 (#00000g)", Encoding::Literal).is_err());
+        // letters are not direction codes and codes are not letters
+        assert!(parse("R 2 (#00000d)", Encoding::Hex).is_err());
+        assert!(parse("1 2 (#000000)", Encoding::Literal).is_err());
+        assert_eq!(
+            parse("R 2 (#00002d)", Encoding::Literal).unwrap().direction,
+            Direction::Right
+        );
+
+        let mut out = vec![];
+        assert!(convert(
+            "R 2000000 (#000000)".as_bytes(),
+            &mut out,
+            Encoding::Literal,
+            Encoding::Hex
+        )
+        .is_err());
+        let mut out = vec![];
+        convert(
+            "R 1048575 (#000000)".as_bytes(),
+            &mut out,
+            Encoding::Literal,
+            Encoding::Hex,
+        )
+        .unwrap();
+        assert_eq!(out, b"R 1048575 (#fffff0)\n");
+    }
 }
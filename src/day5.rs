@@ -1,4 +1,10 @@
-use std::{io::BufRead, ops::Range};
+use anyhow::{bail, Context};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::BufRead,
+    ops::Range,
+};
 
 #[derive(Debug)]
 struct Map {
@@ -10,82 +16,350 @@ impl Map {
         Self { items: Vec::new() }
     }
 
-    pub fn add(&mut self, dst_start: u64, src_start: u64, len: u64) {
+    pub fn add(&mut self, dst_start: u64, src_start: u64, len: u64) -> Result<(), anyhow::Error> {
+        if len == 0 {
+            bail!("zero length range");
+        }
+        let src_end = src_start
+            .checked_add(len)
+            .with_context(|| format!("source {} + length {} overflows", src_start, len))?;
+        let dst_end = dst_start
+            .checked_add(len)
+            .with_context(|| format!("destination {} + length {} overflows", dst_start, len))?;
+        // offsets are kept as i64
+        if src_end > i64::MAX as u64 || dst_end > i64::MAX as u64 {
+            bail!("values bigger than {} are not supported", i64::MAX);
+        }
         let offset: i64 = dst_start as i64 - src_start as i64;
-        self.items.push((src_start..src_start + len, offset));
+        self.items.push((src_start..src_end, offset));
+        Ok(())
     }
 
-    pub fn map(&self, src: &Range<u64>) -> Vec<Range<u64>> {
+    /// Splits source range into parts covered by single item, with offset of that item
+    /// (0 for parts not covered by any item). Items must be sorted.
+    fn split(&self, src: &Range<u64>) -> Vec<(Range<u64>, i64)> {
         let mut result = vec![];
-        let mut elem = Some(src.clone());
+        let mut start = src.start;
         for (range, offset) in &self.items {
-            if let Some(ref input) = elem {
-                // end is before the range => map as identity as there is no mapping
-                if input.end < range.start {
-                    result.push(input.clone());
-                    elem = None;
-                    break;
-                // start is after range => do nothing an continue
-                } else if input.start >= range.end {
-                    continue;
-                } else {
-                    let before = input.start..range.start;
-                    if !before.is_empty() {
-                        result.push(before);
-                    }
-                    let within = input.start..input.end.min(range.end);
-                    if !within.is_empty() {
-                        let mapped = (within.start as i64 + *offset) as u64
-                            ..(within.end as i64 + *offset) as u64;
-                        result.push(mapped);
-                    }
-                    let after = range.end..input.end;
-                    if !after.is_empty() {
-                        elem = Some(after);
-                    } else {
-                        elem = None;
-                        break;
-                    }
-                }
-            } else {
+            if start >= src.end || range.start >= src.end {
                 break;
             }
+            if range.end <= start {
+                continue;
+            }
+            // gap before the range => identity
+            if range.start > start {
+                result.push((start..range.start, 0));
+                start = range.start;
+            }
+            let end = range.end.min(src.end);
+            result.push((start..end, *offset));
+            start = end;
         }
-
-        if let Some(last) = elem {
-            result.push(last);
+        if start < src.end {
+            result.push((start..src.end, 0));
         }
-
         result
     }
 
+    pub fn map(&self, src: &Range<u64>) -> Vec<Range<u64>> {
+        self.split(src)
+            .into_iter()
+            .map(|(range, offset)| shift(&range, offset))
+            .collect()
+    }
+
+    /// Maps single value, items must be sorted
+    pub fn lookup(&self, value: u64) -> u64 {
+        let idx = self
+            .items
+            .partition_point(|(range, _)| range.start <= value);
+        match idx.checked_sub(1).map(|i| &self.items[i]) {
+            Some((range, offset)) if range.contains(&value) => (value as i64 + offset) as u64,
+            _ => value,
+        }
+    }
+
     pub fn sort(&mut self) {
         self.items.sort_by(|a, b| a.0.start.cmp(&b.0.start));
     }
+
+    /// Sorts items, fills gaps with identity and merges neighbouring items with same offset,
+    /// so that items cover whole u64 range
+    pub fn normalize(&mut self) {
+        self.sort();
+        let full = 0..u64::MAX;
+        let mut items: Vec<(Range<u64>, i64)> = vec![];
+        for (range, offset) in self.split(&full) {
+            match items.last_mut() {
+                Some((last, last_offset)) if *last_offset == offset && last.end == range.start => {
+                    last.end = range.end
+                }
+                _ => items.push((range, offset)),
+            }
+        }
+        self.items = items;
+    }
+
+    /// Map equivalent to applying this map and then the next one
+    pub fn compose(&self, next: &Map) -> Map {
+        let mut first = Map {
+            items: self.items.clone(),
+        };
+        first.normalize();
+        let mut composed = Map::new();
+        for (range, offset) in first.items {
+            let image = shift(&range, offset);
+            for (part, next_offset) in next.split(&image) {
+                composed
+                    .items
+                    .push((shift(&part, -offset), offset + next_offset));
+            }
+        }
+        composed.normalize();
+        composed
+    }
+
+    /// Composes chain of maps into one normalized map
+    pub fn chain<'a>(maps: impl IntoIterator<Item = &'a Map>) -> Map {
+        let mut identity = Map::new();
+        identity.normalize();
+        maps.into_iter()
+            .fold(identity, |composed, map| composed.compose(map))
+    }
+}
+
+impl Map {
+    /// Inverse relation, mapping results back to source values
+    pub fn invert(&self) -> InverseMap {
+        let mut normalized = Map {
+            items: self.items.clone(),
+        };
+        normalized.normalize();
+        let mut items: Vec<_> = normalized
+            .items
+            .iter()
+            .map(|(range, offset)| (shift(range, *offset), -offset))
+            .collect();
+        items.sort_by_key(|(range, _)| range.start);
+        InverseMap { items }
+    }
+}
+
+/// Inverse of a map - as map may not be injective, one value can come from many sources,
+/// or from none
+#[derive(Debug)]
+struct InverseMap {
+    /// Image range and offset back to the source, ranges may overlap
+    items: Vec<(Range<u64>, i64)>,
+}
+
+impl InverseMap {
+    /// Source ranges mapping into given range, sorted and merged
+    pub fn map(&self, target: &Range<u64>) -> Vec<Range<u64>> {
+        let mut sources: Vec<Range<u64>> = self
+            .items
+            .iter()
+            .filter_map(|(range, offset)| {
+                let common = range.start.max(target.start)..range.end.min(target.end);
+                (!common.is_empty()).then(|| shift(&common, *offset))
+            })
+            .collect();
+        sources.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<u64>> = vec![];
+        for range in sources {
+            match merged.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (range, offset) in &self.items {
+            writeln!(f, "{:>20}..{:<20} {:+}", range.start, range.end, offset)?;
+        }
+        Ok(())
+    }
+}
+
+fn shift(range: &Range<u64>, offset: i64) -> Range<u64> {
+    (range.start as i64 + offset) as u64..(range.end as i64 + offset) as u64
 }
 
-fn collect_numbers(line: &str) -> Vec<u64> {
+fn collect_numbers(line: &str) -> Result<Vec<u64>, std::num::ParseIntError> {
     line.split(" ")
         .filter(|s| !s.is_empty())
-        .map(|s| s.parse().unwrap())
+        .map(|s| s.parse())
         .collect()
 }
 
-fn parse_map(mut lines: impl Iterator<Item = String>, name: &str) -> Map {
-    let header = lines.next().unwrap();
-    let header = header.split(" ").next().unwrap();
-    assert_eq!(header, name);
+/// Parses one `X-to-Y map:` section from numbered lines, None if there are no more sections.
+/// Fails with all problems found in the section - bad lines, empty or overflowing ranges
+/// and overlapping source ranges.
+fn parse_map(
+    lines: &mut impl Iterator<Item = (usize, String)>,
+) -> Result<Option<(String, String, Map)>, anyhow::Error> {
+    let (header_no, header) = match lines.find(|(_, l)| !l.is_empty()) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let (from, to) = header
+        .strip_suffix(" map:")
+        .and_then(|name| name.split_once("-to-"))
+        .with_context(|| format!("Invalid map header on line {}: {}", header_no + 1, header))?;
     let mut map = Map::new();
-    while let Some(line) = lines.next() {
+    let mut issues = vec![];
+    let mut sources = vec![];
+    for (no, line) in lines.by_ref() {
         if line.is_empty() {
             break;
         }
-        let numbers = collect_numbers(&line);
+        let numbers = match collect_numbers(&line) {
+            Ok(numbers) if numbers.len() == 3 => numbers,
+            Ok(_) => {
+                issues.push(format!(
+                    "line {}: expected 3 numbers, got: {}",
+                    no + 1,
+                    line
+                ));
+                continue;
+            }
+            Err(e) => {
+                issues.push(format!("line {}: {}", no + 1, e));
+                continue;
+            }
+        };
+
+        match map.add(numbers[0], numbers[1], numbers[2]) {
+            Ok(()) => sources.push((no, numbers[1]..numbers[1] + numbers[2])),
+            Err(e) => issues.push(format!("line {}: {}", no + 1, e)),
+        }
+    }
 
-        map.add(numbers[0], numbers[1], numbers[2]);
+    sources.sort_by_key(|(_, range)| range.start);
+    // source range reaching farthest so far
+    let mut farthest: Option<(usize, Range<u64>)> = None;
+    for (no, range) in sources {
+        if let Some((prev_no, prev)) = &farthest {
+            if range.start < prev.end {
+                issues.push(format!(
+                    "line {}: source {:?} overlaps source {:?} from line {}",
+                    no + 1,
+                    range,
+                    prev,
+                    prev_no + 1
+                ));
+            }
+            if range.end <= prev.end {
+                continue;
+            }
+        }
+        farthest = Some((no, range));
+    }
+
+    if !issues.is_empty() {
+        bail!(
+            "Invalid {}-to-{} map on line {}:\n{}",
+            from,
+            to,
+            header_no + 1,
+            issues.join("\n")
+        );
     }
     map.sort();
-    map
+    Ok(Some((from.to_string(), to.to_string(), map)))
+}
+
+/// Seeds and maps between categories
+#[derive(Debug)]
+struct Almanac {
+    seeds: Vec<u64>,
+    /// Map from category -> (to category, map)
+    maps: HashMap<String, Vec<(String, Map)>>,
+}
+
+impl Almanac {
+    fn parse(lines: impl Iterator<Item = String>) -> Result<Self, anyhow::Error> {
+        let mut lines = lines.enumerate();
+        let (_, line) = lines.next().context("Empty almanac")?;
+        let seeds = line
+            .strip_prefix("seeds:")
+            .context("Missing seeds")?
+            .split_whitespace()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<u64>, _>>()?;
+        let mut maps: HashMap<String, Vec<(String, Map)>> = HashMap::new();
+        while let Some((from, to, map)) = parse_map(&mut lines)? {
+            maps.entry(from).or_default().push((to, map));
+        }
+        Ok(Almanac { seeds, maps })
+    }
+
+    /// Maps on the shortest path between categories (breadth first search)
+    fn path(&self, from: &str, to: &str) -> Result<Vec<&Map>, anyhow::Error> {
+        let mut previous: HashMap<&str, (&str, &Map)> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(category) = queue.pop_front() {
+            if category == to {
+                let mut path = vec![];
+                let mut current = to;
+                while current != from {
+                    let (prev, map) = previous[current];
+                    path.push(map);
+                    current = prev;
+                }
+                path.reverse();
+                return Ok(path);
+            }
+            for (next, map) in self.maps.get(category).into_iter().flatten() {
+                if next != from && !previous.contains_key(next.as_str()) {
+                    previous.insert(next, (category, map));
+                    queue.push_back(next);
+                }
+            }
+        }
+        bail!("No maps lead from {} to {}", from, to)
+    }
+
+    /// Single map from one category to another
+    fn chain(&self, from: &str, to: &str) -> Result<Map, anyhow::Error> {
+        Ok(Map::chain(self.path(from, to)?))
+    }
+
+    fn map_ranges(
+        &self,
+        from: &str,
+        to: &str,
+        ranges: &[Range<u64>],
+    ) -> Result<Vec<Range<u64>>, anyhow::Error> {
+        let map = self.chain(from, to)?;
+        Ok(ranges.iter().flat_map(|r| map.map(r)).collect())
+    }
+
+    /// Values of category `from`, which map to given value of category `to`
+    fn reverse_lookup(&self, from: &str, to: &str, value: u64) -> Result<Vec<u64>, anyhow::Error> {
+        let sources = self.trace_back(from, to, &(value..value + 1))?;
+        Ok(sources.into_iter().flatten().collect())
+    }
+
+    /// Ranges of category `from`, which map into given range of category `to`
+    fn trace_back(
+        &self,
+        from: &str,
+        to: &str,
+        range: &Range<u64>,
+    ) -> Result<Vec<Range<u64>>, anyhow::Error> {
+        Ok(self.chain(from, to)?.invert().map(range))
+    }
+
+    /// Seeds line read as pairs of start and length
+    fn seed_ranges(&self) -> Vec<Range<u64>> {
+        self.seeds.chunks(2).map(|c| c[0]..c[0] + c[1]).collect()
+    }
 }
 
 // pub fn fifth_task_1(f: impl BufRead) -> u64 {
@@ -110,65 +384,41 @@ fn parse_map(mut lines: impl Iterator<Item = String>, name: &str) -> Map {
 // }
 
 pub fn fifth_task_2(f: impl BufRead) -> u64 {
-    let mut lines = f.lines().map(|l| l.unwrap());
-
-    let line = lines.next().unwrap();
-    let mut seeds_line = line.split(":");
-    assert_eq!(seeds_line.next().unwrap(), "seeds");
-    let seeds: Vec<u64> = seeds_line
-        .next()
-        .unwrap()
-        .trim()
-        .split(" ")
-        .map(|s| s.parse().unwrap())
-        .collect();
-
-    assert!(lines.next().unwrap().is_empty());
-    println!("Seeds: {:?}", seeds);
-    let maps = load_maps(lines);
-    assert!(seeds.len() % 2 == 0);
-    let seeds = seeds.chunks(2).map(|c| c[0]..c[0] + c[1]).collect();
-    let min = find_min(seeds, maps);
+    let lines = f.lines().map(|l| l.unwrap());
+    let almanac = Almanac::parse(lines).unwrap();
+    println!("Seeds: {:?}", almanac.seeds);
+    assert!(almanac.seeds.len() % 2 == 0);
+    let seeds = almanac.seed_ranges();
+    let composed = almanac.chain("seed", "location").unwrap();
+    println!("seed-to-location:\n{}", composed);
+    let min = find_min(&seeds, &composed);
+    if let Some(seed) = explain_min(&seeds, &composed, min) {
+        println!("Minimal location {} comes from seed {}", min, seed);
+    }
     min
 }
 
-fn find_min(mut seeds: Vec<Range<u64>>, maps: Vec<Map>) -> u64 {
-    let mut min = u64::MAX;
-    let mut results = vec![];
-    for map in &maps {
-        for seed in &seeds {
-            let new_val = map.map(seed);
-            results.extend(new_val);
-        }
-        println!("Mapping {:?} -> {:?}", seeds, results);
-        seeds = results;
-        results = vec![];
-    }
-    for seed in seeds {
-        if seed.start < min {
-            min = seed.start;
-        }
-    }
-    min
+/// Finds seed, which maps to given location
+fn explain_min(seeds: &[Range<u64>], map: &Map, location: u64) -> Option<u64> {
+    let sources = map.invert().map(&(location..location + 1));
+    sources
+        .iter()
+        .flat_map(|source| {
+            seeds.iter().filter_map(move |seed| {
+                let common = seed.start.max(source.start)..seed.end.min(source.end);
+                (!common.is_empty()).then_some(common.start)
+            })
+        })
+        .min()
 }
 
-fn load_maps(mut lines: impl Iterator<Item = String>) -> Vec<Map> {
-    const MAP_NAMES: &[&str] = &[
-        "seed-to-soil",
-        "soil-to-fertilizer",
-        "fertilizer-to-water",
-        "water-to-light",
-        "light-to-temperature",
-        "temperature-to-humidity",
-        "humidity-to-location",
-    ];
-    let mut maps = vec![];
-    for name in MAP_NAMES {
-        let map = parse_map(&mut lines, name);
-        println!("{}: {:?}", name, map);
-        maps.push(map);
-    }
-    maps
+fn find_min(seeds: &[Range<u64>], map: &Map) -> u64 {
+    seeds
+        .iter()
+        .flat_map(|seed| map.map(seed))
+        .map(|r| r.start)
+        .min()
+        .unwrap_or(u64::MAX)
 }
 
 #[cfg(test)]
@@ -180,10 +430,132 @@ mod tests {
         let map_str = "seed-to-soil map:
 50 98 2
 52 50 48";
-        let mut lines = map_str.lines().map(|l| l.to_string());
-        let map = parse_map(&mut lines, "seed-to-soil");
+        let mut lines = map_str.lines().map(|l| l.to_string()).enumerate();
+        let (from, to, map) = parse_map(&mut lines).unwrap().unwrap();
+        assert_eq!((from.as_str(), to.as_str()), ("seed", "soil"));
         assert_eq!(map.map(&(94..100)), vec![96..100, 50..52]);
         assert_eq!(map.map(&(50..52)), vec![52..54]);
         assert_eq!(map.map(&(10..12)), vec![10..12]);
+        assert_eq!(map.map(&(40..60)), vec![40..50, 52..62]);
+    }
+
+    fn sample() -> Almanac {
+        let data = std::fs::read_to_string("data/input-day5-sample.txt").unwrap();
+        Almanac::parse(data.lines().map(|l| l.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_compose() {
+        let almanac = sample();
+        let maps = almanac.path("seed", "location").unwrap();
+        assert_eq!(maps.len(), 7);
+        let composed = Map::chain(maps.iter().copied());
+        for (seed, location) in [(79, 82), (14, 43), (55, 86), (13, 35)] {
+            assert_eq!(composed.lookup(seed), location);
+        }
+        for seed in 0..200 {
+            let expected = maps.iter().fold(seed, |v, m| m.lookup(v));
+            assert_eq!(composed.lookup(seed), expected);
+            assert_eq!(
+                composed.map(&(seed..seed + 1)),
+                vec![expected..expected + 1]
+            );
+        }
+        assert_eq!(composed.items.first().unwrap().0.start, 0);
+        assert_eq!(composed.items.last().unwrap().0.end, u64::MAX);
+        assert_eq!(find_min(&almanac.seed_ranges(), &composed), 46);
+    }
+
+    #[test]
+    fn test_validation() {
+        let data = "seeds: 1 2
+
+seed-to-soil map:
+50 98 2
+52 50 48
+
+soil-to-water map:
+10 20 5
+0 30 0
+100 22 5
+1 18446744073709551615 2
+1 2
+";
+        let err = Almanac::parse(data.lines().map(|l| l.to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Invalid soil-to-water map on line 7:\n"));
+        assert!(err.contains("line 9: zero length range"));
+        assert!(err.contains("line 10: source 22..27 overlaps source 20..25 from line 8"));
+        assert!(err.contains("line 11: source 18446744073709551615 + length 2 overflows"));
+        assert!(err.contains("line 12: expected 3 numbers"));
+        assert!(!err.contains("line 4"));
+    }
+
+    #[test]
+    fn test_invert() {
+        let almanac = sample();
+        let composed = almanac.chain("seed", "location").unwrap();
+        let inverse = composed.invert();
+        for seed in 0..200 {
+            let location = composed.lookup(seed);
+            let sources = inverse.map(&(location..location + 1));
+            assert!(sources.iter().any(|s| s.contains(&seed)));
+            for source in sources.iter().flat_map(|s| s.clone()) {
+                assert_eq!(composed.lookup(source), location);
+            }
+        }
+        // every source of a range maps into the range
+        let target = 40..60;
+        for source in almanac.trace_back("seed", "location", &target).unwrap() {
+            for seed in source {
+                assert!(target.contains(&composed.lookup(seed)));
+            }
+        }
+        assert_eq!(explain_min(&almanac.seed_ranges(), &composed, 46), Some(82));
+    }
+
+    #[test]
+    fn test_categories() {
+        let almanac = sample();
+        assert_eq!(
+            almanac
+                .map_ranges("seed", "humidity", &[79..80, 14..15])
+                .unwrap(),
+            vec![78..79, 43..44]
+        );
+        assert_eq!(
+            almanac
+                .map_ranges("water", "light", &[81..82, 0..1])
+                .unwrap(),
+            vec![74..75, 0..1]
+        );
+        assert_eq!(
+            almanac.reverse_lookup("seed", "location", 82).unwrap(),
+            vec![79]
+        );
+        assert!(almanac.path("location", "seed").is_err());
+
+        // categories do not need to form the fixed chain
+        let data = "seeds: 1 2
+
+a-to-b map:
+10 0 5
+
+a-to-c map:
+0 0 1
+
+b-to-d map:
+20 10 5
+";
+        let almanac = Almanac::parse(data.lines().map(|l| l.to_string())).unwrap();
+        assert_eq!(almanac.chain("a", "d").unwrap().lookup(3), 23);
+        assert_eq!(almanac.chain("a", "c").unwrap().lookup(3), 3);
+        // unmapped values keep their number, so 11 and 21 lead to 21 too
+        assert_eq!(
+            almanac.reverse_lookup("a", "d", 21).unwrap(),
+            vec![1, 11, 21]
+        );
+        assert!(almanac.path("c", "d").is_err());
     }
 }
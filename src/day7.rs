@@ -1,11 +1,13 @@
 use std::{
-    collections::{HashMap, HashSet},
-    io::BufRead,
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Write},
     str::FromStr,
 };
+
+/// Cards in their natural order, strength in game is given by `Rules`
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
 enum Card {
-    Jack, // Jack is Joker amd weakest
     Two,
     Three,
     Four,
@@ -15,11 +17,32 @@ enum Card {
     Eight,
     Nine,
     Ten,
+    Jack,
     Queen,
     King,
     Ace,
 }
 
+impl From<Card> for char {
+    fn from(card: Card) -> char {
+        match card {
+            Card::Two => '2',
+            Card::Three => '3',
+            Card::Four => '4',
+            Card::Five => '5',
+            Card::Six => '6',
+            Card::Seven => '7',
+            Card::Eight => '8',
+            Card::Nine => '9',
+            Card::Ten => 'T',
+            Card::Jack => 'J',
+            Card::Queen => 'Q',
+            Card::King => 'K',
+            Card::Ace => 'A',
+        }
+    }
+}
+
 impl TryFrom<char> for Card {
     type Error = anyhow::Error;
 
@@ -54,69 +77,113 @@ enum Hand {
     FiveOfAKind,
 }
 
-impl TryFrom<&[Card]> for Hand {
-    type Error = anyhow::Error;
-    fn try_from(cards: &[Card]) -> Result<Self, Self::Error> {
-        if cards.len() != 5 {
+/// Rules of the game - which card is wild, how cards are ranked and how many cards are in hand
+#[derive(Debug, Clone)]
+struct Rules {
+    /// Cards from the weakest
+    order: Vec<Card>,
+    wild: Option<Card>,
+    hand_size: usize,
+}
+
+impl Rules {
+    /// Order is given as labels from the weakest card, it must contain every card exactly once
+    fn new(order: &str, wild: Option<char>, hand_size: usize) -> Result<Self, anyhow::Error> {
+        let order = order
+            .chars()
+            .map(Card::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        for card in &order {
+            if order.iter().filter(|c| *c == card).count() > 1 {
+                return Err(anyhow::anyhow!("Card {:?} is ranked twice", card));
+            }
+        }
+        if order.len() != 13 {
+            return Err(anyhow::anyhow!("Order must rank all 13 cards"));
+        }
+        if hand_size == 0 {
+            return Err(anyhow::anyhow!("Hand must have some cards"));
+        }
+        let wild = wild.map(Card::try_from).transpose()?;
+        Ok(Rules {
+            order,
+            wild,
+            hand_size,
+        })
+    }
+
+    /// Part 1 rules
+    fn standard() -> Self {
+        Rules::new("23456789TJQKA", None, 5).unwrap()
+    }
+
+    /// Part 2 rules - jacks are jokers, which are wild and weakest
+    fn jokers() -> Self {
+        Rules::new("J23456789TQKA", Some('J'), 5).unwrap()
+    }
+
+    fn strength(&self, card: Card) -> usize {
+        self.order.iter().position(|c| *c == card).unwrap()
+    }
+
+    /// Best hand, which cards can make
+    fn classify(&self, cards: &[Card]) -> Result<Hand, anyhow::Error> {
+        if cards.len() != self.hand_size {
             return Err(anyhow::anyhow!("Invalid number of cards: {}", cards.len()));
         }
-        let mut groups = HashMap::with_capacity(5);
+        let mut groups = HashMap::with_capacity(cards.len());
+        let mut wilds = 0;
         for card in cards {
-            groups.entry(*card).and_modify(|v| *v += 1).or_insert(1);
+            if Some(*card) == self.wild {
+                wilds += 1;
+            } else {
+                groups.entry(*card).and_modify(|v| *v += 1).or_insert(1);
+            }
         }
-        let number_of_jacks = groups.get(&Card::Jack).copied().unwrap_or(0);
-        let mut counts: Vec<_> = groups.values().copied().collect();
+        let mut counts: Vec<usize> = groups.values().copied().collect();
         counts.sort();
-        let hand = match counts.as_slice() {
-            [1, 1, 1, 1, 1] => {
-                if number_of_jacks >= 1 {
-                    Hand::Pair
-                } else {
-                    Hand::HighCard
-                }
-            }
-            [1, 1, 1, 2] => {
-                if number_of_jacks >= 1 {
-                    Hand::ThreeOfAKind
-                } else {
-                    Hand::Pair
-                }
-            }
-            [1, 2, 2] => {
-                if number_of_jacks == 1 {
-                    Hand::FullHouse
-                } else if number_of_jacks == 2 {
-                    Hand::FourOfAKind
-                } else {
-                    Hand::TwoPairs
-                }
-            }
-            [1, 1, 3] => {
-                if number_of_jacks >= 1 {
-                    Hand::FourOfAKind
-                } else {
-                    Hand::ThreeOfAKind
-                }
-            }
-            [2, 3] => {
-                if number_of_jacks >= 1 {
-                    Hand::FiveOfAKind
-                } else {
-                    Hand::FullHouse
-                }
-            }
-            [1, 4] => {
-                if number_of_jacks >= 1 {
-                    Hand::FiveOfAKind
-                } else {
-                    Hand::FourOfAKind
-                }
-            }
-            [5] => Hand::FiveOfAKind,
+        Ok(Hand::best(counts, wilds))
+    }
+}
 
-            _ => unreachable!("INvalid hand: {:?}", counts),
+impl Display for Hand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Hand::HighCard => "High card",
+            Hand::Pair => "One pair",
+            Hand::TwoPairs => "Two pair",
+            Hand::ThreeOfAKind => "Three of a kind",
+            Hand::FullHouse => "Full house",
+            Hand::FourOfAKind => "Four of a kind",
+            Hand::FiveOfAKind => "Five of a kind",
         };
-        Ok(hand)
+        write!(f, "{}", name)
+    }
+}
+
+impl Hand {
+    /// Hand from sizes of groups of equal cards in ascending order
+    fn from_counts(counts: &[usize]) -> Self {
+        let mut top = counts.iter().rev().copied();
+        match (top.next().unwrap_or(0), top.next().unwrap_or(0)) {
+            (5.., _) => Hand::FiveOfAKind,
+            (4, _) => Hand::FourOfAKind,
+            (3, 2..) => Hand::FullHouse,
+            (3, _) => Hand::ThreeOfAKind,
+            (2, 2) => Hand::TwoPairs,
+            (2, _) => Hand::Pair,
+            _ => Hand::HighCard,
+        }
+    }
+
+    /// Best hand for groups of equal cards (ascending) and number of wild cards.
+    /// Hands are ranked by two largest groups, so wild cards always join the largest one.
+    fn best(mut counts: Vec<usize>, wilds: usize) -> Self {
+        match counts.last_mut() {
+            Some(largest) => *largest += wilds,
+            None => counts.push(wilds),
+        }
+        Hand::from_counts(&counts)
     }
 }
 
@@ -125,54 +192,342 @@ struct Game {
     bet: u64,
     cards: Vec<Card>,
     hand: Hand,
+    /// Strengths of cards for breaking ties
+    strengths: Vec<usize>,
 }
 
-impl FromStr for Game {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Game {
+    fn parse(s: &str, rules: &Rules) -> Result<Self, anyhow::Error> {
         let mut l = s.split_ascii_whitespace();
         let cards = l
             .next()
-            .unwrap()
+            .ok_or_else(|| anyhow::anyhow!("Missing cards"))?
             .chars()
             .map(Card::try_from)
             .collect::<Result<Vec<_>, _>>()?;
-        let bet: u64 = l.next().unwrap().parse()?;
-        let hand = cards.as_slice().try_into()?;
-        Ok(Game { cards, bet, hand })
+        let bet: u64 = l
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Missing bet"))?
+            .parse()?;
+        let hand = rules.classify(&cards)?;
+        let strengths = cards.iter().map(|c| rules.strength(*c)).collect();
+        Ok(Game {
+            cards,
+            bet,
+            hand,
+            strengths,
+        })
     }
 }
 
 impl PartialOrd for Game {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.hand.cmp(&other.hand) {
-            std::cmp::Ordering::Equal => {
-                for idx in 0..self.cards.len() {
-                    match self.cards[idx].cmp(&other.cards[idx]) {
-                        std::cmp::Ordering::Equal => continue,
-                        ord => return Some(ord),
-                    }
-                }
-                Some(std::cmp::Ordering::Equal)
-            }
-            ord => return Some(ord),
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Game {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.hand
+            .cmp(&other.hand)
+            .then_with(|| self.strengths.cmp(&other.strengths))
     }
 }
 
-pub fn seventh_task_2(f: impl BufRead) -> u64 {
-    let mut games: Vec<Game> = f.lines().map(|l| l.unwrap().parse().unwrap()).collect();
+fn winnings(f: impl BufRead, rules: &Rules) -> u64 {
+    let mut games: Vec<Game> = f
+        .lines()
+        .map(|l| Game::parse(&l.unwrap(), rules).unwrap())
+        .collect();
     games.sort();
-    println!("Games: {:?}", games);
     games
         .into_iter()
         .enumerate()
         .fold(0, |acc, (idx, game)| acc + (idx as u64 + 1) * game.bet)
 }
+
+/// One line of ranking report
+#[derive(Debug, PartialEq)]
+struct ReportRow {
+    cards: String,
+    hand: Hand,
+    rank: usize,
+    bet: u64,
+    winnings: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortKey {
+    Rank,
+    Cards,
+    Hand,
+    Bet,
+    Winnings,
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rank" => Ok(SortKey::Rank),
+            "cards" => Ok(SortKey::Cards),
+            "hand" => Ok(SortKey::Hand),
+            "bet" => Ok(SortKey::Bet),
+            "winnings" => Ok(SortKey::Winnings),
+            _ => Err(anyhow::anyhow!("Invalid sort key: {}", s)),
+        }
+    }
+}
+
+/// Games ordered by rank (weakest first) with their contribution to total winnings
+fn report(f: impl BufRead, rules: &Rules) -> Result<Vec<ReportRow>, anyhow::Error> {
+    let mut games = f
+        .lines()
+        .map(|l| Game::parse(&l?, rules))
+        .collect::<Result<Vec<_>, _>>()?;
+    games.sort();
+    Ok(games
+        .into_iter()
+        .enumerate()
+        .map(|(idx, game)| ReportRow {
+            cards: game.cards.iter().map(|c| char::from(*c)).collect(),
+            hand: game.hand,
+            rank: idx + 1,
+            bet: game.bet,
+            winnings: (idx as u64 + 1) * game.bet,
+        })
+        .collect())
+}
+
+/// Stable sort, so rows with equal key stay ordered by rank
+fn sort_report(rows: &mut [ReportRow], key: SortKey, descending: bool) {
+    rows.sort_by(|a, b| {
+        let ord = match key {
+            SortKey::Rank => a.rank.cmp(&b.rank),
+            SortKey::Cards => a.cards.cmp(&b.cards),
+            SortKey::Hand => a.hand.cmp(&b.hand),
+            SortKey::Bet => a.bet.cmp(&b.bet),
+            SortKey::Winnings => a.winnings.cmp(&b.winnings),
+        };
+        if descending {
+            ord.reverse()
+        } else {
+            ord
+        }
+    });
+}
+
+fn write_table(rows: &[ReportRow], mut out: impl Write) -> Result<(), anyhow::Error> {
+    writeln!(
+        out,
+        "{:<8} {:<16} {:>6} {:>6} {:>10}",
+        "Cards", "Hand", "Rank", "Bet", "Winnings"
+    )?;
+    for row in rows {
+        writeln!(
+            out,
+            "{:<8} {:<16} {:>6} {:>6} {:>10}",
+            row.cards,
+            row.hand.to_string(),
+            row.rank,
+            row.bet,
+            row.winnings
+        )?;
+    }
+    Ok(())
+}
+
+fn write_csv(rows: &[ReportRow], mut out: impl Write) -> Result<(), anyhow::Error> {
+    writeln!(out, "cards,hand,rank,bet,winnings")?;
+    for row in rows {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            row.cards, row.hand, row.rank, row.bet, row.winnings
+        )?;
+    }
+    Ok(())
+}
+
+pub fn seventh_task_1(f: impl BufRead) -> u64 {
+    winnings(f, &Rules::standard())
+}
+
+pub fn seventh_task_2(f: impl BufRead) -> u64 {
+    winnings(f, &Rules::jokers())
+}
+
+/// Writes ranking report with part 2 rules sorted by given key as a table and as CSV,
+/// returns total winnings
+pub fn seventh_report(
+    f: impl BufRead,
+    key: SortKey,
+    descending: bool,
+    table: impl Write,
+    csv: impl Write,
+) -> Result<u64, anyhow::Error> {
+    let mut rows = report(f, &Rules::jokers())?;
+    let total = rows.iter().map(|r| r.winnings).sum();
+    sort_report(&mut rows, key, descending);
+    write_table(&rows, table)?;
+    write_csv(&rows, csv)?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const CARDS: [Card; 13] = [
+        Card::Two,
+        Card::Three,
+        Card::Four,
+        Card::Five,
+        Card::Six,
+        Card::Seven,
+        Card::Eight,
+        Card::Nine,
+        Card::Ten,
+        Card::Jack,
+        Card::Queen,
+        Card::King,
+        Card::Ace,
+    ];
+
+    /// Tries every card in place of every wild card
+    fn brute_force(cards: &mut Vec<Card>, wild: Card, from: usize) -> Hand {
+        match cards[from..].iter().position(|c| *c == wild) {
+            None => Rules::new("23456789TJQKA", None, cards.len())
+                .unwrap()
+                .classify(cards)
+                .unwrap(),
+            Some(i) => {
+                let i = from + i;
+                let best = CARDS
+                    .iter()
+                    .map(|c| {
+                        cards[i] = *c;
+                        brute_force(cards, wild, i + 1)
+                    })
+                    .max()
+                    .unwrap();
+                cards[i] = wild;
+                best
+            }
+        }
+    }
+
+    fn hand(cards: &str, rules: &Rules) -> Result<Hand, anyhow::Error> {
+        let cards = cards
+            .chars()
+            .map(Card::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        rules.classify(&cards)
+    }
+
+    #[test]
+    fn test_winnings() {
+        let f =
+            || std::io::BufReader::new(std::fs::File::open("data/input-day7-sample.txt").unwrap());
+        assert_eq!(seventh_task_1(f()), 6440);
+        assert_eq!(seventh_task_2(f()), 5905);
+    }
+
+    #[test]
+    fn test_rules() {
+        let standard = Rules::standard();
+        let jokers = Rules::jokers();
+        assert_eq!(hand("KTJJT", &standard).unwrap(), Hand::TwoPairs);
+        assert_eq!(hand("KTJJT", &jokers).unwrap(), Hand::FourOfAKind);
+        assert_eq!(hand("JJJJJ", &jokers).unwrap(), Hand::FiveOfAKind);
+        assert!(standard.strength(Card::Jack) > standard.strength(Card::Ten));
+        assert_eq!(jokers.strength(Card::Jack), 0);
+        // aces low, threes wild, hands of three cards
+        let variant = Rules::new("A23456789TJQK", Some('3'), 3).unwrap();
+        assert_eq!(variant.strength(Card::Ace), 0);
+        assert_eq!(hand("AKQ", &variant).unwrap(), Hand::HighCard);
+        assert_eq!(hand("AAQ", &variant).unwrap(), Hand::Pair);
+        assert_eq!(hand("AAA", &variant).unwrap(), Hand::ThreeOfAKind);
+        assert_eq!(hand("A3Q", &variant).unwrap(), Hand::Pair);
+        assert_eq!(hand("333", &variant).unwrap(), Hand::ThreeOfAKind);
+        assert!(hand("AAQK", &variant).is_err());
+        let seven = Rules::new("J23456789TQKA", Some('J'), 7).unwrap();
+        assert_eq!(hand("AAKKQQJ", &seven).unwrap(), Hand::FullHouse);
+        assert_eq!(hand("AAKKJJJ", &seven).unwrap(), Hand::FiveOfAKind);
+        assert!(Rules::new("23456789TJQK", None, 5).is_err());
+        assert!(Rules::new("23456789TJQKAA", None, 5).is_err());
+    }
+
+    #[test]
+    fn test_best_hand() {
+        let mut rng = Rng::new(7);
+        let mut next = |n: usize| rng.below(n as u64) as usize;
+        for _ in 0..200 {
+            let size = 1 + next(6);
+            let wild = CARDS[next(13)];
+            let rules = Rules::new("23456789TJQKA", Some(wild.into()), size).unwrap();
+            // small pool of cards, so there are groups and up to 3 wild cards
+            let pool: Vec<Card> = (0..3)
+                .map(|_| CARDS[next(13)])
+                .filter(|c| *c != wild)
+                .chain(Some(if wild == Card::Ace {
+                    Card::King
+                } else {
+                    Card::Ace
+                }))
+                .collect();
+            let mut cards: Vec<Card> = (0..size)
+                .map(|i| {
+                    if i < 3 && next(3) == 0 {
+                        wild
+                    } else {
+                        pool[next(pool.len())]
+                    }
+                })
+                .collect();
+            let expected = brute_force(&mut cards, wild, 0);
+            assert_eq!(rules.classify(&cards).unwrap(), expected, "{:?}", cards);
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let f = std::io::BufReader::new(std::fs::File::open("data/input-day7-sample.txt").unwrap());
+        let mut rows = report(f, &Rules::jokers()).unwrap();
+        assert_eq!(rows.iter().map(|r| r.winnings).sum::<u64>(), 5905);
+        assert_eq!(
+            rows[4],
+            ReportRow {
+                cards: "KTJJT".to_string(),
+                hand: Hand::FourOfAKind,
+                rank: 5,
+                bet: 220,
+                winnings: 1100
+            }
+        );
+        sort_report(&mut rows, SortKey::Winnings, true);
+        assert_eq!(rows[0].cards, "T55J5");
+        sort_report(&mut rows, SortKey::Hand, false);
+        assert_eq!(rows[0].cards, "32T3K");
+        let mut csv = vec![];
+        write_csv(&rows[..1], &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "cards,hand,rank,bet,winnings\n32T3K,One pair,1,765,765\n"
+        );
+
+        let f = std::io::BufReader::new(std::fs::File::open("data/input-day7-sample.txt").unwrap());
+        let (mut table, mut csv) = (vec![], vec![]);
+        let total = seventh_report(f, SortKey::Bet, true, &mut table, &mut csv).unwrap();
+        assert_eq!(total, 5905);
+        assert!(String::from_utf8(table)
+            .unwrap()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("32T3K"));
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 6);
+    }
+}
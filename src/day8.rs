@@ -19,66 +19,264 @@ impl TryFrom<char> for Direction {
     }
 }
 
-struct AdjacentNodes {
-    left: String,
-    right: String,
+type Road = Vec<Direction>;
+type NodeId = usize;
+
+/// Network with node names interned to indices
+#[derive(Debug)]
+struct Graph {
+    names: Vec<String>,
+    ids: HashMap<String, NodeId>,
+    /// Left and right neighbour of every node
+    edges: Vec<[NodeId; 2]>,
 }
-impl AdjacentNodes {
-    fn next(&self, d: Direction) -> &str {
-        match d {
-            Direction::Left => &self.left,
-            Direction::Right => &self.right,
-        }
-    }
+
+lazy_static! {
+    static ref EDGE_RE: regex::Regex = regex::Regex::new(r"^(\w+) = \((\w+), (\w+)\)$").unwrap();
 }
 
-type Road = Vec<Direction>;
-type Graph = HashMap<String, AdjacentNodes>;
+impl Graph {
+    fn intern(&mut self, name: &str) -> NodeId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
 
-lazy_static! {
-    static ref EDGE_RE: regex::Regex = regex::Regex::new(r"(\w+) = \((\w+), (\w+)\)").unwrap();
-}
-fn parse_graph(lines: impl Iterator<Item = String>) -> Graph {
-    let mut graph = Graph::new();
-    for line in lines {
-        let captures = EDGE_RE.captures(&line).unwrap();
-        let from = captures.get(1).unwrap().as_str().to_string();
-        let to = AdjacentNodes {
-            left: captures.get(2).unwrap().as_str().to_string(),
-            right: captures.get(3).unwrap().as_str().to_string(),
+    /// Checks that every node is defined exactly once and all referenced nodes exist
+    /// Lines come with their numbers in the input file, which are used in errors
+    fn parse(lines: impl Iterator<Item = (usize, String)>) -> Result<Self, anyhow::Error> {
+        let mut graph = Graph {
+            names: vec![],
+            ids: HashMap::new(),
+            edges: vec![],
         };
+        let mut defined: Vec<Option<[NodeId; 2]>> = vec![];
+        for (line_no, line) in lines.filter(|(_, l)| !l.trim().is_empty()) {
+            let captures = EDGE_RE
+                .captures(line.trim())
+                .ok_or_else(|| anyhow::anyhow!("Invalid node on line {}: {}", line_no, line))?;
+            let ids = [1, 2, 3].map(|i| graph.intern(captures.get(i).unwrap().as_str()));
+            defined.resize(graph.names.len(), None);
+            if defined[ids[0]].replace([ids[1], ids[2]]).is_some() {
+                return Err(anyhow::anyhow!(
+                    "Node {} is defined twice",
+                    graph.names[ids[0]]
+                ));
+            }
+        }
+        let mut dangling = vec![];
+        for (id, edges) in defined.into_iter().enumerate() {
+            match edges {
+                Some(edges) => graph.edges.push(edges),
+                None => dangling.push(graph.names[id].as_str()),
+            }
+        }
+        if !dangling.is_empty() {
+            return Err(anyhow::anyhow!("Undefined nodes: {}", dangling.join(", ")));
+        }
+        Ok(graph)
+    }
+
+    fn id(&self, name: &str) -> Result<NodeId, anyhow::Error> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown node {}", name))
+    }
+
+    fn name(&self, id: NodeId) -> &str {
+        &self.names[id]
+    }
+
+    fn next(&self, id: NodeId, d: Direction) -> NodeId {
+        match d {
+            Direction::Left => self.edges[id][0],
+            Direction::Right => self.edges[id][1],
+        }
+    }
 
-        graph.insert(from, to);
+    /// Nodes reachable from start by any directions
+    fn reachable(&self, start: NodeId) -> Vec<bool> {
+        let mut visited = vec![false; self.names.len()];
+        visited[start] = true;
+        let mut stack = vec![start];
+        while let Some(id) = stack.pop() {
+            for next in self.edges[id] {
+                if !visited[next] {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        visited
     }
+}
 
-    graph
+/// Number of steps from one node to the other following the road
+fn steps_between(graph: &Graph, road: &Road, from: &str, to: &str) -> Result<u64, anyhow::Error> {
+    let (start, goal) = (graph.id(from)?, graph.id(to)?);
+    if !graph.reachable(start)[goal] {
+        return Err(anyhow::anyhow!("Goal {} unreachable from {}", to, from));
+    }
+    let cycle = Cycle::analyze(graph, road, start, |id| id == goal);
+    cycle
+        .tail_goals
+        .iter()
+        .chain(&cycle.cycle_goals)
+        .map(|g| g.step)
+        .min()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Goal {} unreachable from {} following the instructions",
+                to,
+                from
+            )
+        })
 }
 
 pub fn eighth_task_1(f: impl BufRead) -> u64 {
+    let (road, graph) = parse_input(f).unwrap();
+    steps_between(&graph, &road, "AAA", "ZZZ").unwrap()
+}
+
+fn parse_input(f: impl BufRead) -> Result<(Road, Graph), anyhow::Error> {
     let mut lines = f.lines();
     let road = lines
         .next()
-        .unwrap()
-        .unwrap()
+        .ok_or_else(|| anyhow::anyhow!("Missing instructions"))??
+        .trim()
         .chars()
         .map(Direction::try_from)
-        .collect::<Result<Road, _>>()
-        .unwrap();
-    //empty line
-    lines.next();
-    let graph = parse_graph(lines.map(|l| l.unwrap()));
-    let mut steps = 0;
-    let mut current = "AAA";
-    loop {
-        for d in &road {
-            let next = graph.get(current).unwrap().next(*d);
-            steps += 1;
-            if next == "ZZZ" {
-                return steps;
+        .collect::<Result<Road, _>>()?;
+    if road.is_empty() {
+        return Err(anyhow::anyhow!("Missing instructions"));
+    }
+    let lines = lines
+        .enumerate()
+        .map(|(no, l)| l.map(|l| (no + 2, l)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let graph = Graph::parse(lines.into_iter())?;
+    Ok((road, graph))
+}
+
+/// Visit of a goal node, step is counted from the start, position is index into road
+#[derive(Debug, PartialEq, Clone)]
+struct Goal {
+    step: u64,
+    position: usize,
+    node: NodeId,
+}
+
+/// Walk from one node - after `tail` steps the walk repeats every `length` steps
+/// (same node at same position in road)
+#[derive(Debug)]
+struct Cycle {
+    start: NodeId,
+    tail: u64,
+    length: u64,
+    /// Goals visited only once, before the cycle
+    tail_goals: Vec<Goal>,
+    /// Goals in first round of the cycle, they repeat every `length` steps
+    cycle_goals: Vec<Goal>,
+}
+
+impl Cycle {
+    fn analyze(
+        graph: &Graph,
+        road: &Road,
+        start: NodeId,
+        is_goal: impl Fn(NodeId) -> bool,
+    ) -> Self {
+        let mut seen: HashMap<(NodeId, usize), u64> = HashMap::new();
+        let mut goals = vec![];
+        let mut current = start;
+        let mut step = 0;
+        let tail = loop {
+            let position = (step % road.len() as u64) as usize;
+            if step > 0 && is_goal(current) {
+                goals.push(Goal {
+                    step,
+                    position,
+                    node: current,
+                });
+            }
+            if let Some(first) = seen.insert((current, position), step) {
+                break first;
             }
-            current = next;
+            current = graph.next(current, road[position]);
+            step += 1;
+        };
+        let length = step - tail;
+        // step 0 is not a goal, its repetition is in the first round instead
+        let first_round = tail.max(1);
+        let (tail_goals, cycle_goals) = goals
+            .into_iter()
+            .filter(|g| g.step < first_round + length)
+            .partition(|g| g.step < first_round);
+        Cycle {
+            start,
+            tail,
+            length,
+            tail_goals,
+            cycle_goals,
         }
     }
+
+    fn is_goal(&self, step: u64) -> bool {
+        self.tail_goals.iter().any(|g| g.step == step)
+            || self
+                .cycle_goals
+                .iter()
+                .any(|g| step >= g.step && (step - g.step).is_multiple_of(self.length))
+    }
+}
+
+impl Cycle {
+    /// Cycle with node names from the graph
+    fn display<'a>(&'a self, graph: &'a Graph) -> CycleDisplay<'a> {
+        CycleDisplay { cycle: self, graph }
+    }
+}
+
+struct CycleDisplay<'a> {
+    cycle: &'a Cycle,
+    graph: &'a Graph,
+}
+
+impl std::fmt::Display for CycleDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (cycle, graph) = (self.cycle, self.graph);
+        write!(
+            f,
+            "{}: tail {}, cycle {}, goals",
+            graph.name(cycle.start),
+            cycle.tail,
+            cycle.length
+        )?;
+        for g in &cycle.tail_goals {
+            write!(
+                f,
+                " {} at {} (once, position {})",
+                graph.name(g.node),
+                g.step,
+                g.position
+            )?;
+        }
+        for g in &cycle.cycle_goals {
+            write!(
+                f,
+                " {} at {} (position {})",
+                graph.name(g.node),
+                g.step,
+                g.position
+            )?;
+        }
+        Ok(())
+    }
 }
 
 fn gcd(x: u64, y: u64) -> u64 {
@@ -99,47 +297,260 @@ fn gcd(x: u64, y: u64) -> u64 {
     a
 }
 
-fn gcm(nums: &[u64]) -> u64 {
-    nums.into_iter()
-        .copied()
-        .reduce(|x, y| x * y / gcd(x, y))
-        .unwrap()
+/// Returns (g, x, y) such that a*x + b*y = g = gcd(a, b)
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
 }
-pub fn eighth_task_2(f: impl BufRead) -> u64 {
-    let mut lines = f.lines();
-    let road = lines
-        .next()
-        .unwrap()
-        .unwrap()
-        .chars()
-        .map(Direction::try_from)
-        .collect::<Result<Road, _>>()
-        .unwrap();
-    //empty line
-    lines.next();
-    let graph = parse_graph(lines.map(|l| l.unwrap()));
-    let starting_nodes: Vec<_> = graph
-        .keys()
-        .filter(|k| k.ends_with('A'))
-        .map(|s| s.as_str())
-        .collect();
-    let mut loops = vec![];
-    for node in &starting_nodes {
-        let mut current = *node;
-
-        let mut steps = 0;
-        'outer: loop {
-            for d in &road {
-                let next = graph.get(current).unwrap().next(*d);
-                steps += 1;
-                if next.ends_with('Z') {
-                    loops.push(steps);
-                    break 'outer;
+
+/// Combines t ≡ r1 (mod m1) and t ≡ r2 (mod m2) into t ≡ r (mod lcm), moduli need not be coprime.
+/// None if congruences have no common solution, error if lcm does not fit u64.
+fn crt((r1, m1): (u64, u64), (r2, m2): (u64, u64)) -> Result<Option<(u64, u64)>, anyhow::Error> {
+    let g = gcd(m1, m2);
+    let diff = r2 as i128 - r1 as i128;
+    if diff % g as i128 != 0 {
+        return Ok(None);
+    }
+    let lcm = u64::try_from(m1 as u128 / g as u128 * m2 as u128).map_err(|_| {
+        anyhow::anyhow!("Combined cycle of lengths {} and {} overflows u64", m1, m2)
+    })?;
+    let (_, x, _) = extended_gcd(m1 as i128, m2 as i128);
+    // k solves m1 / g * k ≡ diff / g (mod m2 / g), all below 2^64, so products fit u128
+    let m = m2 as i128 / g as i128;
+    let k = ((diff / g as i128).rem_euclid(m) as u128 * x.rem_euclid(m) as u128) % m as u128;
+    let r = ((r1 as u128 + m1 as u128 * k) % lcm as u128) as u64;
+    Ok(Some((r, lcm)))
+}
+
+/// First step, when all walks are in a goal at the same time
+fn first_common_goal(graph: &Graph, cycles: &[Cycle]) -> Result<u64, anyhow::Error> {
+    if cycles.is_empty() {
+        return Err(anyhow::anyhow!("No start nodes"));
+    }
+    for c in cycles {
+        if c.tail_goals.is_empty() && c.cycle_goals.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Walk from {} never reaches a goal",
+                graph.name(c.start)
+            ));
+        }
+    }
+    // goals in tails happen only once, so can be just checked
+    let mut best = cycles
+        .iter()
+        .flat_map(|c| c.tail_goals.iter().map(|g| g.step))
+        .filter(|step| cycles.iter().all(|c| c.is_goal(*step)))
+        .min();
+    // otherwise all walks are in their cycles - (residue, modulus, lowest valid step)
+    let mut congruences = vec![(0, 1, 0)];
+    for c in cycles {
+        let mut merged = vec![];
+        for (r, m, low) in &congruences {
+            for g in &c.cycle_goals {
+                if let Some((r, m)) = crt((*r, *m), (g.step % c.length, c.length))? {
+                    merged.push((r, m, g.step.max(*low)));
                 }
-                current = next;
             }
         }
+        merged.sort();
+        merged.dedup();
+        congruences = merged;
+    }
+    for (r, m, low) in congruences {
+        // smallest step >= low with step ≡ r (mod m)
+        let (r, m, low) = (r as u128, m as u128, low as u128);
+        let step = u64::try_from(low + (r + m - low % m) % m)
+            .map_err(|_| anyhow::anyhow!("Common step overflows u64"))?;
+        best = Some(best.map_or(step, |b| b.min(step)));
+    }
+    best.ok_or_else(|| anyhow::anyhow!("Walks are never in goals at the same time"))
+}
+
+fn ghost_cycles(graph: &Graph, road: &Road) -> Vec<Cycle> {
+    let mut starting_nodes: Vec<_> = (0..graph.names.len())
+        .filter(|id| graph.name(*id).ends_with('A'))
+        .collect();
+    starting_nodes.sort_by_key(|id| graph.name(*id));
+    starting_nodes
+        .into_iter()
+        .map(|node| Cycle::analyze(graph, road, node, |id| graph.name(id).ends_with('Z')))
+        .collect()
+}
+
+pub fn eighth_task_2(f: impl BufRead) -> u64 {
+    let (road, graph) = parse_input(f).unwrap();
+    let cycles = ghost_cycles(&graph, &road);
+    for cycle in &cycles {
+        println!("{}", cycle.display(&graph));
+    }
+    first_common_goal(&graph, &cycles).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> (Road, Graph) {
+        parse_input(s.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_sample() {
+        let f =
+            std::io::BufReader::new(std::fs::File::open("data/input-day8-sample3.txt").unwrap());
+        assert_eq!(eighth_task_2(f), 6);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let (road, graph) = parse(
+            "L\n\n\
+             1A = (1B, 1B)\n\
+             1B = (1Z, 1Z)\n\
+             1Z = (1C, 1C)\n\
+             1C = (1D, 1D)\n\
+             1D = (1B, 1B)\n\
+             2A = (2Z, 2Z)\n\
+             2Z = (2B, 2B)\n\
+             2B = (2Z, 2Z)\n",
+        );
+        let cycles = ghost_cycles(&graph, &road);
+        // 1A -> 1B -> 1Z -> 1C -> 1D -> 1B, goal at 2, 6, 10, ...
+        assert_eq!((cycles[0].tail, cycles[0].length), (1, 4));
+        assert_eq!(cycles[0].cycle_goals[0].step, 2);
+        // 2Z at odd steps
+        assert_eq!((cycles[1].tail, cycles[1].length), (1, 2));
+        assert_eq!(cycles[1].cycle_goals[0].step, 1);
+        // goals are not aligned with cycle length, lcm of first goals (2) would be wrong
+        assert!(first_common_goal(&graph, &cycles).is_err());
+
+        let (road, graph) = parse(
+            "LR\n\n\
+             1A = (1Z, 1Z)\n\
+             1Z = (1B, 1B)\n\
+             1B = (1B, 1C)\n\
+             1C = (1Z, 1Z)\n\
+             2A = (2B, 2B)\n\
+             2B = (2Z, 2Z)\n\
+             2Z = (2C, 2C)\n\
+             2C = (2B, 2B)\n",
+        );
+        let cycles = ghost_cycles(&graph, &road);
+        // 1Z at steps 1, 5, 9, ... always at position 1
+        assert_eq!((cycles[0].tail, cycles[0].length), (1, 4));
+        assert!(cycles[0].tail_goals.is_empty());
+        assert_eq!(
+            cycles[0]
+                .cycle_goals
+                .iter()
+                .map(|g| (g.step, g.position))
+                .collect::<Vec<_>>(),
+            vec![(1, 1)]
+        );
+        // 2Z at steps 2, 5, 8, ..., cycle has to cover both positions in road
+        assert_eq!((cycles[1].tail, cycles[1].length), (1, 6));
+        assert_eq!(cycles[1].cycle_goals.len(), 2);
+        assert_eq!(
+            cycles[0].display(&graph).to_string(),
+            "1A: tail 1, cycle 4, goals 1Z at 1 (position 1)"
+        );
+        assert_eq!(first_common_goal(&graph, &cycles).unwrap(), 5);
+
+        // goal only before the cycle
+        let (road, graph) = parse(
+            "L\n\n1A = (1Z, 1Z)\n1Z = (1B, 1B)\n1B = (1B, 1B)\n2A = (2Z, 2Z)\n2Z = (2Z, 2Z)\n",
+        );
+        let cycles = ghost_cycles(&graph, &road);
+        assert_eq!(cycles[0].tail_goals[0].step, 1);
+        assert!(cycles[0].cycle_goals.is_empty());
+        assert_eq!(first_common_goal(&graph, &cycles).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_crt() {
+        assert_eq!(crt((2, 3), (3, 5)).unwrap(), Some((8, 15)));
+        assert_eq!(crt((1, 4), (3, 6)).unwrap(), Some((9, 12)));
+        assert_eq!(crt((1, 4), (2, 6)).unwrap(), None);
+        // coprime moduli always have a solution, but it does not fit u64
+        assert!(crt((1, 1 << 40), (2, (1 << 40) - 1)).is_err());
+        let big = (1 << 32) - 5;
+        assert_eq!(crt((1, 1 << 32), (2, big)).unwrap().unwrap().1, big << 32);
+
+        let (_, graph) = parse("L\n\n1A = (1A, 1A)\n2A = (2A, 2A)\nZZZ = (ZZZ, ZZZ)\n");
+        let cycle = |start: NodeId, length: u64| Cycle {
+            start,
+            tail: 0,
+            length,
+            tail_goals: vec![],
+            cycle_goals: vec![Goal {
+                step: 1,
+                position: 0,
+                node: 2,
+            }],
+        };
+        let cycles = vec![cycle(0, 1 << 40), cycle(1, (1 << 40) - 1)];
+        let err = first_common_goal(&graph, &cycles).unwrap_err().to_string();
+        assert!(err.contains("overflows"), "{}", err);
+        assert_eq!(first_common_goal(&graph, &cycles[..1]).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unreachable_goal() {
+        let (road, graph) = parse("L\n\n1A = (1B, 1B)\n1B = (1A, 1A)\n");
+        let cycles = ghost_cycles(&graph, &road);
+        assert_eq!(
+            first_common_goal(&graph, &cycles).unwrap_err().to_string(),
+            "Walk from 1A never reaches a goal"
+        );
+    }
+
+    #[test]
+    fn test_no_start_nodes() {
+        let (road, graph) = parse("L\n\nBBB = (BBB, BBB)\n");
+        let cycles = ghost_cycles(&graph, &road);
+        assert_eq!(
+            first_common_goal(&graph, &cycles).unwrap_err().to_string(),
+            "No start nodes"
+        );
+    }
+
+    #[test]
+    fn test_graph() {
+        let f =
+            std::io::BufReader::new(std::fs::File::open("data/input-day8-sample1.txt").unwrap());
+        assert_eq!(eighth_task_1(f), 2);
+        let (road, graph) = parse("LR\n\nAAA = (BBB, AAA)\nBBB = (AAA, ZZZ)\nZZZ = (ZZZ, ZZZ)\n");
+        assert_eq!(graph.names, vec!["AAA", "BBB", "ZZZ"]);
+        assert_eq!(graph.next(graph.id("BBB").unwrap(), Direction::Right), 2);
+        assert_eq!(steps_between(&graph, &road, "AAA", "ZZZ").unwrap(), 2);
+        assert_eq!(
+            steps_between(&graph, &road, "ZZZ", "AAA")
+                .unwrap_err()
+                .to_string(),
+            "Goal AAA unreachable from ZZZ"
+        );
+        assert!(steps_between(&graph, &road, "AAA", "YYY").is_err());
+        // right turn in AAA never happens
+        let (road, graph) = parse("L\n\nAAA = (BBB, ZZZ)\nBBB = (AAA, AAA)\nZZZ = (ZZZ, ZZZ)\n");
+        assert!(steps_between(&graph, &road, "AAA", "ZZZ")
+            .unwrap_err()
+            .to_string()
+            .contains("following the instructions"));
+    }
+
+    #[test]
+    fn test_invalid_graph() {
+        let err = parse_input("L\n\nAAA = (BBB, CCC)\nBBB = (BBB, BBB)\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Undefined nodes: CCC");
+        let err = parse_input("L\n\nAAA = (AAA, AAA)\nAAA = (AAA, AAA)\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Node AAA is defined twice");
+        let err = parse_input("L\n\nAAA = (AAA, AAA)\nAAA = AAA\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid node on line 4: AAA = AAA");
+        assert!(parse_input("LX\n\nAAA = (AAA, AAA)\n".as_bytes()).is_err());
+        assert!(parse_input("\n\nAAA = (AAA, AAA)\n".as_bytes()).is_err());
     }
-    println!("Loops {:?}", loops);
-    gcm(&loops)
 }
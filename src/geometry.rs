@@ -0,0 +1,205 @@
+//! Geometry of lattice polygons, shared by days working with loops on a grid.
+//!
+//! Polygon is a list of vertices (row, column), it can be closed (last vertex equal to the first one)
+//! or not - closing edge is then implied.
+
+pub type Point = (i64, i64);
+
+fn edges(poly: &[Point]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    let closing = match (poly.first(), poly.last()) {
+        (Some(first), Some(last)) if first != last => Some((*last, *first)),
+        _ => None,
+    };
+    poly.windows(2).map(|w| (w[0], w[1])).chain(closing)
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Twice the area of polygon (shoelace formula), always integer for lattice polygons
+pub fn double_area(poly: &[Point]) -> i64 {
+    edges(poly)
+        .map(|(a, b)| a.1 * b.0 - b.1 * a.0)
+        .sum::<i64>()
+        .abs()
+}
+
+/// Area of polygon, rounded down
+pub fn area(poly: &[Point]) -> i64 {
+    double_area(poly) / 2
+}
+
+/// Number of lattice points on polygon boundary - for polygons with axis parallel edges
+/// this is the length of perimeter
+pub fn perimeter(poly: &[Point]) -> i64 {
+    edges(poly).map(|(a, b)| gcd(b.0 - a.0, b.1 - a.1)).sum()
+}
+
+/// Number of lattice points strictly inside polygon - from Pick's theorem A = I + B/2 - 1
+pub fn interior_points(poly: &[Point]) -> i64 {
+    (double_area(poly) - perimeter(poly) + 2) / 2
+}
+
+/// Is point on some edge of polygon
+pub fn on_boundary(poly: &[Point], p: Point) -> bool {
+    edges(poly).any(|(a, b)| on_segment(a, b, p))
+}
+
+/// Is point strictly inside polygon - ray casting towards increasing column
+pub fn contains(poly: &[Point], p: Point) -> bool {
+    if on_boundary(poly, p) {
+        return false;
+    }
+    let mut inside = false;
+    for (a, b) in edges(poly) {
+        if (a.0 > p.0) != (b.0 > p.0) {
+            // sign of cross product tells on which side of the edge the point is
+            let cross = (b.1 - a.1) * (p.0 - a.0) - (p.1 - a.1) * (b.0 - a.0);
+            if (cross > 0) == (b.0 > a.0) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn cross(o: Point, a: Point, b: Point) -> i64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn on_segment(a: Point, b: Point, p: Point) -> bool {
+    cross(a, b, p) == 0
+        && p.0 >= a.0.min(b.0)
+        && p.0 <= a.0.max(b.0)
+        && p.1 >= a.1.min(b.1)
+        && p.1 <= a.1.max(b.1)
+}
+
+/// Do segments have any common point
+fn segments_touch(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let d1 = cross(c, d, a).signum();
+    let d2 = cross(c, d, b).signum();
+    let d3 = cross(a, b, c).signum();
+    let d4 = cross(a, b, d).signum();
+    if d1 * d2 < 0 && d3 * d4 < 0 {
+        return true;
+    }
+    on_segment(c, d, a) || on_segment(c, d, b) || on_segment(a, b, c) || on_segment(a, b, d)
+}
+
+/// Pairs of edges (indexed by their starting vertex), which cross or overlap each other.
+/// Neighbouring edges may only share their common vertex, zero length edges are ignored.
+pub fn self_intersections(poly: &[Point]) -> Vec<(usize, usize)> {
+    // edges around zero length edge are neighbours, so they are compacted out first
+    let edges: Vec<_> = edges(poly)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .collect();
+    let n = edges.len();
+    let mut res = vec![];
+    for i in 0..n {
+        let (index_i, (a, b)) = edges[i];
+        for (j, (index_j, (c, d))) in edges.iter().copied().enumerate().skip(i + 1) {
+            let neighbours = j == i + 1 || (i == 0 && j == n - 1);
+            let bad = if neighbours {
+                // shared vertex is fine, unless the second edge goes back over the first one
+                let (u, v) = ((b.0 - a.0, b.1 - a.1), (d.0 - c.0, d.1 - c.1));
+                u.0 * v.1 - u.1 * v.0 == 0 && u.0 * v.0 + u.1 * v.1 < 0
+            } else {
+                segments_touch(a, b, c, d)
+            };
+            if bad {
+                res.push((index_i, index_j));
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square() {
+        let open = vec![(0, 0), (0, 2), (2, 2), (2, 0)];
+        let mut closed = open.clone();
+        closed.push((0, 0));
+        for p in [open, closed] {
+            assert_eq!(area(&p), 4);
+            assert_eq!(perimeter(&p), 8);
+            assert_eq!(interior_points(&p), 1);
+            assert!(contains(&p, (1, 1)));
+            assert!(!contains(&p, (0, 1)));
+            assert!(on_boundary(&p, (0, 1)));
+            assert!(!contains(&p, (3, 1)));
+        }
+    }
+
+    #[test]
+    fn test_concave() {
+        // U shape
+        let p = vec![
+            (0, 0),
+            (0, 6),
+            (4, 6),
+            (4, 4),
+            (2, 4),
+            (2, 2),
+            (4, 2),
+            (4, 0),
+        ];
+        assert_eq!(area(&p), 20);
+        assert_eq!(perimeter(&p), 24);
+        assert_eq!(interior_points(&p), 9);
+        assert!(contains(&p, (3, 1)));
+        assert!(!contains(&p, (3, 3)));
+        assert!(contains(&p, (1, 3)));
+    }
+
+    #[test]
+    fn test_diagonal() {
+        let p = vec![(0, 0), (4, 4), (0, 4)];
+        assert_eq!(double_area(&p), 16);
+        assert_eq!(perimeter(&p), 12);
+        assert_eq!(interior_points(&p), 3);
+        assert!(on_boundary(&p, (2, 2)));
+        assert!(contains(&p, (1, 2)));
+        assert!(!contains(&p, (3, 2)));
+    }
+
+    #[test]
+    fn test_self_intersections() {
+        let square = vec![(0, 0), (0, 2), (2, 2), (2, 0), (0, 0)];
+        assert!(self_intersections(&square).is_empty());
+        // figure eight - diagonal edges cross
+        let eight = vec![(0, 0), (0, 2), (2, 0), (2, 2), (0, 0)];
+        assert_eq!(self_intersections(&eight), vec![(1, 3)]);
+        // going back over the same edge
+        let back = vec![(0, 0), (0, 3), (0, 1), (2, 1), (2, 0), (0, 0)];
+        assert!(self_intersections(&back).contains(&(0, 1)));
+        // touching in a vertex
+        let touch = vec![
+            (0, 0),
+            (0, 4),
+            (2, 4),
+            (2, 2),
+            (0, 2),
+            (-2, 2),
+            (-2, 0),
+            (0, 0),
+        ];
+        assert_eq!(self_intersections(&touch), vec![(0, 3), (0, 4)]);
+        // repeated vertices do not break neighbourhood of edges around them
+        let repeated = vec![(0, 0), (0, 2), (0, 2), (2, 2), (2, 0), (0, 0), (0, 0)];
+        assert!(self_intersections(&repeated).is_empty());
+        // but indexes still refer to the original edges
+        let eight = vec![(0, 0), (0, 0), (0, 2), (2, 0), (2, 2), (0, 0)];
+        assert_eq!(self_intersections(&eight), vec![(2, 4)]);
+    }
+}
@@ -0,0 +1,29 @@
+//! Random numbers for generated inputs and sampling, shared by days which need them.
+
+/// Simple xorshift random generator
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform number in 0..n
+    pub fn below(&mut self, n: u64) -> u64 {
+        // reject values from incomplete last block to keep distribution uniform
+        let limit = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next();
+            if x < limit {
+                return x % n;
+            }
+        }
+    }
+}